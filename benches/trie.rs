@@ -0,0 +1,49 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use fr_trie::glob::acl::{Acl, AclTrie, Permissions};
+use fr_trie::glob::GlobMatcher;
+
+/// Paths sharing prefixes at several levels, as ACLs usually do
+fn paths(count: usize) -> Vec<String> {
+    (0..count)
+        .map(|index| format!("/tenant-{}/project-{}/resource-{}/item", index % 16, index % 97, index))
+        .collect()
+}
+
+fn acl_trie(paths: &[String]) -> AclTrie {
+    let mut trie = AclTrie::new();
+    for path in paths {
+        trie.insert(Acl::new(path), Permissions::READ);
+    }
+    trie.insert(Acl::new("/tenant-1/*"), Permissions::WRITE);
+    trie
+}
+
+fn insert_benchmark(c: &mut Criterion) {
+    let paths = paths(5_000);
+    c.bench_function("acl_insert_5000", |b| b.iter(|| acl_trie(black_box(&paths))));
+}
+
+fn lookup_benchmark(c: &mut Criterion) {
+    let paths = paths(5_000);
+    let trie = acl_trie(&paths);
+    let queries: Vec<Acl> = paths.iter().step_by(50).map(|path| Acl::new(path)).collect();
+    c.bench_function("acl_get_exact_100", |b| b.iter(|| {
+        for query in queries.iter() {
+            black_box(trie.get_exact(query));
+        }
+    }));
+    c.bench_function("acl_get_merge_100", |b| b.iter(|| {
+        for query in queries.iter() {
+            black_box(trie.get_merge::<GlobMatcher>(query));
+        }
+    }));
+    let paths: Vec<&str> = paths.iter().step_by(50).map(String::as_str).collect();
+    c.bench_function("acl_get_merge_str_100", |b| b.iter(|| {
+        for path in paths.iter() {
+            black_box(trie.get_merge::<GlobMatcher>(*path));
+        }
+    }));
+}
+
+criterion_group!(benches, insert_benchmark, lookup_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,127 @@
+//! The Trie entry API for in-place value manipulation
+use crate::key::KeyPrefix;
+use crate::trie::Trie;
+
+/// A view into a single key of a [Trie], either occupied or vacant
+pub enum Entry<'a, K, V> where
+    K: KeyPrefix + Clone, V: Clone
+{
+    Occupied(OccupiedEntry<'a, K, V>),
+    Vacant(VacantEntry<'a, K, V>),
+}
+
+/// An entry whose key is already stored
+pub struct OccupiedEntry<'a, K, V> where
+    K: KeyPrefix + Clone, V: Clone
+{
+    pub(crate) key: K,
+    pub(crate) value: &'a mut V,
+}
+
+/// An entry whose key is not stored yet
+pub struct VacantEntry<'a, K, V> where
+    K: KeyPrefix + Clone, V: Clone
+{
+    pub(crate) key: K,
+    pub(crate) trie: &'a mut Trie<K, V>,
+}
+
+impl<'a, K: KeyPrefix + Clone, V: Clone> Entry<'a, K, V> {
+
+    /// The key of this entry
+    #[inline]
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(entry) => entry.key(),
+            Entry::Vacant(entry) => entry.key(),
+        }
+    }
+
+    /// Inserts `default` if vacant and returns the stored value
+    #[inline]
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default),
+        }
+    }
+
+    /// Inserts the result of `default` if vacant and returns the stored value
+    #[inline]
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    /// Inserts the default value if vacant and returns the stored value
+    #[inline]
+    pub fn or_default(self) -> &'a mut V
+        where V: Default
+    {
+        self.or_insert_with(V::default)
+    }
+
+    /// Modifies the stored value in place if occupied
+    #[inline]
+    pub fn and_modify<F: FnOnce(&mut V)>(self, f: F) -> Self {
+        match self {
+            Entry::Occupied(mut entry) => {
+                f(entry.get_mut());
+                Entry::Occupied(entry)
+            }
+            Entry::Vacant(entry) => Entry::Vacant(entry),
+        }
+    }
+}
+
+impl<'a, K: KeyPrefix + Clone, V: Clone> OccupiedEntry<'a, K, V> {
+
+    #[inline]
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    #[inline]
+    pub fn get(&self) -> &V {
+        self.value
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut V {
+        self.value
+    }
+
+    /// Converts the entry into a reference bound to the trie lifetime
+    #[inline]
+    pub fn into_mut(self) -> &'a mut V {
+        self.value
+    }
+
+    /// Replaces the stored value, returning the previous one
+    #[inline]
+    pub fn insert(&mut self, value: V) -> V {
+        std::mem::replace(self.value, value)
+    }
+}
+
+impl<'a, K: KeyPrefix + Clone, V: Clone> VacantEntry<'a, K, V> {
+
+    #[inline]
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    #[inline]
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    /// Stores `value` under the entry key and returns a reference to it
+    #[inline]
+    pub fn insert(self, value: V) -> &'a mut V {
+        self.trie.insert(self.key.clone(), value);
+        self.trie.get_mut(&self.key).unwrap()
+    }
+}
@@ -0,0 +1,360 @@
+//! Key folding: case insensitivity and Unicode normalization
+//!
+//! A [Folding] configured on a trie applies to the stored and the match keys alike, while
+//! [CaseInsensitive] folds case on the fly, inside any matcher.
+use std::sync::Arc;
+use serde::{Serialize, Deserialize};
+use unicode_normalization::UnicodeNormalization;
+use crate::matcher::{Capture, CharClass, Event, MatchType, PushdownStateMachine, RegexNode, State, StateSequence};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Normalization {
+    /// Canonical composition
+    Nfc,
+    /// Compatibility composition (e.g. `ﬁ` is `fi`)
+    Nfkc,
+}
+
+/// How keys are folded before being compared. The default folding leaves keys untouched
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Folding {
+    pub case_insensitive: bool,
+    pub normalization: Option<Normalization>,
+}
+
+impl Folding {
+    pub fn new(case_insensitive: bool, normalization: Option<Normalization>) -> Self {
+        Self {
+            case_insensitive,
+            normalization,
+        }
+    }
+
+    #[inline]
+    pub fn is_identity(&self) -> bool {
+        !self.case_insensitive && self.normalization.is_none()
+    }
+
+    /// Folds `text`: normalized, then lowercased (and normalized again, as lowercasing may decompose)
+    pub fn fold(&self, text: &str) -> String {
+        let folded = self.normalize(text);
+        if self.case_insensitive {
+            self.normalize(&folded.to_lowercase())
+        }
+        else {
+            folded
+        }
+    }
+
+    /// Folds the literal chars of a pattern `text`, leaving its `syntax` chars as they are. Literal chars
+    /// folding into syntax (e.g. NFKC folds `＊` into `*`) get escaped, or else their run is left unfolded
+    /// for syntaxes without `escape`, so folding never turns a literal into a wildcard.
+    /// Escaped chars are folded as literals
+    pub fn fold_pattern(&self, text: &str, syntax: impl Fn(char) -> bool, escape: Option<char>) -> String {
+        let mut folded = String::new();
+        let mut literal = String::new();
+        let mut chars = text.chars();
+        while let Some(ch) = chars.next() {
+            if Some(ch) == escape {
+                if let Some(escaped) = chars.next() {
+                    literal.push(escaped);
+                    continue;
+                }
+            }
+            if syntax(ch) || Some(ch) == escape {
+                self.push_literal(&mut folded, &literal, &syntax, escape);
+                literal.clear();
+                folded.push(ch);
+            }
+            else {
+                literal.push(ch);
+            }
+        }
+        self.push_literal(&mut folded, &literal, &syntax, escape);
+        folded
+    }
+
+    /// Pushes a run of `literal` chars of a pattern, folded
+    fn push_literal(&self, folded: &mut String, literal: &str, syntax: &impl Fn(char) -> bool, escape: Option<char>) {
+        let folded_literal = self.fold(literal);
+        match escape {
+            Some(escape) => {
+                for ch in folded_literal.chars() {
+                    if syntax(ch) || ch == escape {
+                        folded.push(escape);
+                    }
+                    folded.push(ch);
+                }
+            }
+            None if folded_literal.chars().any(&syntax) => folded.push_str(literal),
+            None => folded.push_str(&folded_literal),
+        }
+    }
+
+    #[inline]
+    fn normalize(&self, text: &str) -> String {
+        match self.normalization {
+            None => text.to_string(),
+            Some(Normalization::Nfc) => text.nfc().collect(),
+            Some(Normalization::Nfkc) => text.nfkc().collect(),
+        }
+    }
+}
+
+/////////////////////////////
+/// Wraps a matcher so that it ignores case, lowercasing both the key tokens and the match key.
+/// Class ranges get their lowercase counterparts added. Captures index the match key as given,
+/// even where lowercasing expands a char (e.g. `İ`)
+#[derive(Debug, Clone)]
+pub struct CaseInsensitive<M> {
+    inner: M,
+    /// The match key fed so far on every level
+    stack: Vec<Lowercased>,
+}
+
+/// A match key fed lowercased
+#[derive(Debug, Clone, Default)]
+struct Lowercased {
+    /// Number of chars fed to the inner matcher
+    len: usize,
+    /// Offset and length of the runs of chars fed for a single match key char
+    expansions: Vec<(usize, usize)>,
+}
+
+impl Lowercased {
+
+    /// Offset in the match key of an `offset` in the lowercased match key. An `end` offset within an
+    /// expansion stands after its char
+    fn original(&self, offset: usize, end: bool) -> usize {
+        let mut shift = 0;
+        for (start, len) in self.expansions.iter() {
+            if offset <= *start {
+                break;
+            }
+            if offset < start + len {
+                return start - shift + usize::from(end);
+            }
+            shift += len - 1;
+        }
+        offset - shift
+    }
+}
+
+impl<M: PushdownStateMachine> PushdownStateMachine for CaseInsensitive<M> {
+    fn new() -> Self {
+        Self {
+            inner: M::new(),
+            stack: Vec::new(),
+        }
+    }
+
+    #[inline]
+    fn step_in(&mut self, sequence: &Vec<Arc<StateSequence>>) {
+        let folded: Vec<Arc<StateSequence>> = sequence.iter()
+            .map(|token| Arc::new(StateSequence {
+                match_type: lowercase_match_type(&token.match_type),
+                sequence: lowercase(&token.sequence),
+            }))
+            .collect();
+        self.stack.push(self.stack.last().cloned().unwrap_or_default());
+        self.inner.step_in(&folded)
+    }
+
+    #[inline]
+    fn step_out(&mut self) {
+        self.stack.pop();
+        self.inner.step_out()
+    }
+
+    #[inline]
+    fn accepts_more(&self) -> bool {
+        self.inner.accepts_more()
+    }
+
+    #[inline]
+    fn feed(&mut self, ev: Event) {
+        match ev {
+            Event::CharIn(ch) => {
+                let mut len = 0;
+                for lower in ch.to_lowercase() {
+                    self.inner.feed(Event::CharIn(lower));
+                    len += 1;
+                }
+                if let Some(fed) = self.stack.last_mut() {
+                    if len > 1 {
+                        fed.expansions.push((fed.len, len));
+                    }
+                    fed.len += len;
+                }
+            }
+            Event::EndOfStream => self.inner.feed(ev),
+        }
+    }
+
+    #[inline]
+    fn state(&self) -> State {
+        self.inner.state()
+    }
+
+    #[inline]
+    fn is_sink(&self) -> bool {
+        self.inner.is_sink()
+    }
+
+    #[inline]
+    fn captures(&self) -> Vec<Capture> {
+        let captures = self.inner.captures();
+        match self.stack.last() {
+            Some(fed) if !fed.expansions.is_empty() => captures.into_iter()
+                .map(|capture| Capture {
+                    start: fed.original(capture.start, false),
+                    end: fed.original(capture.end, true),
+                })
+                .collect(),
+            _ => captures,
+        }
+    }
+
+    #[inline]
+    fn cost(&self) -> usize {
+        self.inner.cost()
+    }
+}
+
+fn lowercase(chars: &[char]) -> Vec<char> {
+    chars.iter().flat_map(|ch| ch.to_lowercase()).collect()
+}
+
+/// Lowercase counterpart of a single char, if it has one
+fn lowercase_char(ch: char) -> Option<char> {
+    let mut lower = ch.to_lowercase();
+    match (lower.next(), lower.next()) {
+        (Some(lower), None) if lower != ch => Some(lower),
+        _ => None,
+    }
+}
+
+fn lowercase_class(class: &CharClass) -> CharClass {
+    let mut ranges = class.ranges.clone();
+    for (low, high) in class.ranges.iter() {
+        if let (Some(low), Some(high)) = (lowercase_char(*low), lowercase_char(*high)) {
+            ranges.push((low, high));
+        }
+    }
+    CharClass { negated: class.negated, ranges }
+}
+
+fn lowercase_regex(node: &RegexNode) -> RegexNode {
+    match node {
+        RegexNode::Char(ch) => match lowercase(&[*ch]).as_slice() {
+            [lower] => RegexNode::Char(*lower),
+            lower => RegexNode::Group(vec![lower.iter().map(|ch| RegexNode::Char(*ch)).collect()]),
+        },
+        RegexNode::Any => RegexNode::Any,
+        RegexNode::Class(class) => RegexNode::Class(lowercase_class(class)),
+        RegexNode::Group(branches) => RegexNode::Group(branches.iter()
+            .map(|branch| branch.iter().map(lowercase_regex).collect())
+            .collect()),
+        RegexNode::Repeat { node, min, max } => RegexNode::Repeat {
+            node: Box::new(lowercase_regex(node)),
+            min: *min,
+            max: *max,
+        },
+    }
+}
+
+fn lowercase_match_type(match_type: &MatchType) -> MatchType {
+    match match_type {
+        MatchType::Class(class) => MatchType::Class(lowercase_class(class)),
+        MatchType::Alternation(branches) => MatchType::Alternation(branches.iter()
+            .map(|branch| lowercase(branch))
+            .collect()),
+        MatchType::Regex(node) => MatchType::Regex(lowercase_regex(node)),
+        match_type => match_type.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::trie::Trie;
+    use crate::glob::GlobMatcher;
+    use crate::glob::acl::{Acl, AclTrie, Permissions};
+    use crate::topic::TopicMatcher;
+    use crate::topic::mqtt::MqttTopic;
+    use crate::fold::*;
+
+    #[test]
+    fn folding_test() {
+        let mut trie = AclTrie::with_folding(Folding::new(true, Some(Normalization::Nfc)));
+        trie.insert(Acl::new("/Docs/*"), Permissions::READ);
+        trie.insert(Acl::new("/CAFÉ/[A-C]*"), Permissions::WRITE);
+        assert_eq!(Some(Permissions::READ), trie.get_merge::<GlobMatcher>(&Acl::new("/docs/Readme")));
+        assert_eq!(Some(Permissions::READ), trie.get_merge::<GlobMatcher>(&Acl::new("/DOCS/x")));
+        // Decomposed `é`
+        assert_eq!(Some(Permissions::WRITE), trie.get_merge::<GlobMatcher>(&Acl::new("/cafe\u{301}/beans")));
+        assert_eq!(None, trie.get_merge::<GlobMatcher>(&Acl::new("/café/Decaf")));
+        assert!(trie.contains_key(&Acl::new("/docs/*")));
+        assert_eq!(vec![Acl::new("/café/[a-c]*"), Acl::new("/docs/*")], trie.keys().collect::<Vec<_>>());
+        assert_eq!(Some(Permissions::READ), trie.remove(&Acl::new("/DoCs/*")));
+
+        let mut trie = Trie::with_folding(Folding::new(false, Some(Normalization::Nfkc)));
+        trie.insert(String::from("ﬁle"), 1);
+        assert_eq!(Some(&1), trie.get_exact(&String::from("file")));
+        assert_eq!(None, trie.get_exact(&String::from("File")));
+        // Tries folding differently are told apart, even holding the same keys
+        let plain: Trie<String, i32> = trie.iter().map(|(key, value)| (key, *value)).collect();
+        assert_ne!(trie, plain);
+        assert_eq!(trie, trie.clone());
+
+        // Folding inside the matcher, the stored keys being left untouched
+        let mut trie = AclTrie::new();
+        trie.insert(Acl::new("/Docs/[A-C]*.{TXT,md}"), Permissions::READ);
+        assert_eq!(None, trie.get_merge::<GlobMatcher>(&Acl::new("/docs/a.txt")));
+        assert_eq!(Some(Permissions::READ), trie.get_merge::<CaseInsensitive<GlobMatcher>>(&Acl::new("/docs/a.txt")));
+        assert_eq!(Some(Permissions::READ), trie.get_merge::<CaseInsensitive<GlobMatcher>>(&Acl::new("/DOCS/B.MD")));
+        assert_eq!(None, trie.get_merge::<CaseInsensitive<GlobMatcher>>(&Acl::new("/docs/d.txt")));
+    }
+
+    #[test]
+    fn folding_captures_test() {
+        let captured = |trie: &AclTrie, query: &str| trie.get_with_captures::<GlobMatcher>(query)
+            .map(|(_, _, captures)| captures.iter().map(|capture| capture.slice(query).to_string()).collect::<Vec<_>>());
+
+        // Captures index the query as given, not as folded
+        let mut trie = AclTrie::with_folding(Folding::new(false, Some(Normalization::Nfc)));
+        trie.insert(Acl::new("/*/x"), Permissions::READ);
+        assert_eq!(Some(vec![String::from("cafe\u{301}")]), captured(&trie, "/cafe\u{301}/x"));
+        let mut trie = AclTrie::with_folding(Folding::new(true, None));
+        trie.insert(Acl::new("/*/X"), Permissions::READ);
+        assert_eq!(Some(vec![String::from("İab")]), captured(&trie, "/İab/x"));
+        assert_eq!(Some(vec![String::from("ab")]), captured(&trie, "/ab/x"));
+
+        // Lowercasing `İ` feeds two chars to the matcher
+        let mut trie = AclTrie::new();
+        trie.insert(Acl::new("/*/x"), Permissions::READ);
+        let query = "/İab/x";
+        let (_, _, captures) = trie.get_with_captures::<CaseInsensitive<GlobMatcher>>(query).unwrap();
+        assert_eq!(vec!["İab"], captures.iter().map(|capture| capture.slice(query)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn folding_literals_test() {
+        // A fullwidth `＊` folds into an escaped, literal `*`
+        let mut trie = AclTrie::with_folding(Folding::new(true, Some(Normalization::Nfkc)));
+        trie.insert(Acl::new("/Files/＊"), Permissions::READ);
+        trie.insert(Acl::new("/Files/[＊Ａ]"), Permissions::WRITE);
+        assert_eq!(vec![Acl::new("/files/[\\*a]"), Acl::new("/files/\\*")], trie.keys().collect::<Vec<_>>());
+        assert_eq!(None, trie.get_merge::<GlobMatcher>("/files/readme"));
+        assert_eq!(Some(Permissions::READ | Permissions::WRITE), trie.get_merge::<GlobMatcher>("/FILES/＊"));
+        assert_eq!(Some(Permissions::WRITE), trie.get_merge::<GlobMatcher>("/files/Ａ"));
+        assert!(trie.contains_key(&Acl::new("/files/＊")));
+
+        // Topics have no escaping: levels folding into wildcards are left unfolded
+        let mut trie = Trie::with_folding(Folding::new(false, Some(Normalization::Nfkc)));
+        trie.insert(MqttTopic::new("sport/＋"), 1);
+        trie.insert(MqttTopic::new("ﬁnance/+"), 2);
+        assert_eq!(None, trie.get::<TopicMatcher>("sport/tennis"));
+        assert_eq!(Some(1), trie.get::<TopicMatcher>("sport/＋"));
+        assert_eq!(Some(2), trie.get::<TopicMatcher>("finance/stock"));
+    }
+}
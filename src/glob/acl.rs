@@ -5,10 +5,12 @@ use std::sync::Arc;
 use bitflags::bitflags;
 use serde::{Serialize, Deserialize};
 use crate::trie::Trie;
-use crate::key::{KeyPrefix, ValueMerge};
-use crate::matcher::{MatchType, StateSequence};
+use crate::fold::Folding;
+use crate::key::{advance, text_key_prefix, KeyPrefix, KeyQuery, ValueMerge};
+use crate::matcher::{CharClass, MatchType, SequenceBuilder, StateSequence};
+use crate::glob::intersection;
 
-#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Acl {
     pub path: String,
 }
@@ -31,75 +33,159 @@ impl Display for Acl {
 
 impl KeyPrefix for Acl {
 
-    #[inline]
-    fn key_chars(&self) -> Vec<char> {
-        self.path.chars().collect::<Vec<_>>()
-    }
-
-    #[inline]
-    fn key_len(&self) -> usize {
-        self.path.len()
-    }
+    text_key_prefix!(path);
 
+    /// Only literal chars get folded
     #[inline]
-    fn empty() -> Self {
+    fn folded(&self, folding: &Folding) -> Self {
         Self {
-            path: String::new(),
+            path: folding.fold_pattern(&self.path, Self::is_syntax, Some('\\'))
         }
     }
 
+    /// Match keys have no syntax: all their chars get folded
     #[inline]
-    fn new_from_key_prefix(&self, index: usize) -> Self {
+    fn folded_query(&self, folding: &Folding) -> Self {
         Self {
-            path: self.path[..index].to_string()
+            path: folding.fold(&self.path)
         }
     }
 
+    /// Walks the key tokens up to `index`, without allocating
     #[inline]
-    fn new_from_postfix(&self, index: usize) -> Self {
-        Self {
-            path: self.path[index..].to_string()
+    fn prefix_boundary(&self, index: usize) -> usize {
+        let mut chars = self.path.chars();
+        let mut boundary = 0;
+        while boundary < index && chars.clone().next().is_some() {
+            let token_len = Self::token_len(chars.clone());
+            if boundary + token_len > index {
+                break;
+            }
+            boundary += token_len;
+            advance(&mut chars, token_len);
         }
+        boundary
     }
 
+    /// Compiles the glob syntax: `*` (any chars), `**` (any chars, across segments), `?` (any char), `[a-z]` / `[!0-9]` (classes),
+    /// `{json,yaml}` (alternation) and `\` escaping the next char.
+    /// Unterminated classes and alternations are kept as literals.
     #[inline]
     fn compiled(&self) -> Vec<Arc<StateSequence>> {
-        let mut compiled_seq = Vec::new();
-        let mut buff: Vec<char> = Vec::new();
-        let mut next_state: MatchType = MatchType::Literal;
-        for ch in self.path.chars() {
-            if ch == '*' {
-                if buff.len() > 0 {
-                    compiled_seq.push(Arc::new(StateSequence {
-                        match_type: next_state.clone(),
-                        sequence: buff,
-                    }));
-                    next_state = MatchType::AnyOr;
+        let mut builder = SequenceBuilder::new();
+        let chars: Vec<char> = self.symbols().collect();
+        let mut position = 0;
+        while position < chars.len() {
+            let mut token_len = Self::token_len(chars[position..].iter().copied());
+            let construct = match chars[position] {
+                '\\' if token_len == 2 => {
+                    builder.literal(chars[position + 1]);
+                    None
                 }
-                else {
-                    next_state = MatchType::AnyOr;
+                '*' => {
+                    // The char kept along with `**` is compiled as a literal
+                    token_len = Self::stars_len(chars[position..].iter().copied());
+                    Some(if token_len > 1 { MatchType::DeepAnyOr } else { MatchType::AnyOr })
                 }
-                buff = Vec::new();
-            }
-            else {
-                buff.push(ch);
+                '?' => Some(MatchType::AnyChar),
+                '[' if token_len > 1 => {
+                    CharClass::parse(&chars[position + 1..]).map(|(class, _)| MatchType::Class(class))
+                }
+                '{' if token_len > 1 => {
+                    Self::parse_alternation(&chars[position + 1..]).map(|(branches, _)| MatchType::Alternation(branches))
+                }
+                ch => {
+                    builder.literal(ch);
+                    None
+                }
+            };
+            if let Some(match_type) = construct {
+                builder.construct(match_type);
             }
+            position += token_len;
         }
-        if !buff.is_empty() {
-            compiled_seq.push(Arc::new(StateSequence {
-                match_type: next_state,
-                sequence: buff,
-            }));
-        }
-        else if next_state == MatchType::AnyOr {
-            compiled_seq.push(Arc::new(StateSequence {
-                match_type: next_state,
-                sequence: Vec::new(),
-            }));
+        builder.build()
+    }
+
+}
+
+impl KeyQuery<Acl> for str {
+
+    #[inline]
+    fn query_symbols(&self) -> impl Iterator<Item = char> + Clone + '_ {
+        self.chars()
+    }
+
+    #[inline]
+    fn to_key(&self) -> Acl {
+        Acl::new(self)
+    }
+}
+
+impl Acl {
+
+    /// Length of the glob token starting `chars`.
+    /// A `**` is kept along with the plain char following it, so `**/` is never split across nodes
+    fn token_len<I: Iterator<Item = char> + Clone>(chars: I) -> usize {
+        let mut rest = chars.clone();
+        match rest.next() {
+            Some('\\') if rest.clone().next().is_some() => 2,
+            Some('*') => {
+                let stars = Self::stars_len(chars.clone());
+                match chars.clone().nth(stars) {
+                    Some(ch) if stars > 1 && !matches!(ch, '\\' | '?' | '[' | '{') => stars + 1,
+                    _ => stars,
+                }
+            }
+            Some('[') => CharClass::scan(rest, |_, _| {}).map_or(1, |(_, len)| len + 1),
+            Some('{') => Self::scan_alternation(rest, |_| {}).map_or(1, |len| len + 1),
+            _ => 1,
         }
-        compiled_seq
     }
 
+    /// Whether `ch` may be part of the glob syntax, depending on where it stands
+    #[inline]
+    fn is_syntax(ch: char) -> bool {
+        matches!(ch, '*' | '?' | '[' | ']' | '!' | '^' | '-' | '{' | ',' | '}')
+    }
+
+    /// Number of consecutive `*` starting `chars`
+    fn stars_len(chars: impl Iterator<Item = char>) -> usize {
+        chars.take_while(|ch| *ch == '*').count()
+    }
+
+    /// Parses an alternation following `{`, returning its branches along with the consumed chars (`}` included)
+    fn parse_alternation(chars: &[char]) -> Option<(Vec<Vec<char>>, usize)> {
+        let mut branches = Vec::new();
+        let mut branch = Vec::new();
+        let len = Self::scan_alternation(chars.iter().copied(), |ch| match ch {
+            Some(ch) => branch.push(ch),
+            None => branches.push(std::mem::take(&mut branch)),
+        })?;
+        Some((branches, len))
+    }
+
+    /// Scans an alternation following `{` as [Acl::parse_alternation] does, handing `on_char` the
+    /// chars of its branches, each one ended by `None`. Returns the consumed chars
+    fn scan_alternation(chars: impl Iterator<Item = char>, mut on_char: impl FnMut(Option<char>)) -> Option<usize> {
+        let mut chars = chars;
+        let mut position = 0;
+        loop {
+            position += 1;
+            match chars.next()? {
+                '}' => {
+                    on_char(None);
+                    return Some(position);
+                }
+                ',' => on_char(None),
+                '\\' => {
+                    position += 1;
+                    on_char(Some(chars.next()?));
+                }
+                ch => on_char(Some(ch)),
+            }
+        }
+    }
 }
 
 ///////////////////
@@ -129,16 +215,178 @@ impl ValueMerge for Permissions {
 
 pub type AclTrie = Trie<Acl,Permissions>;
 
+impl<V: Clone> Trie<Acl, V> {
+
+    /// Stored rules matching at least one path the `acl` pattern matches too, along with their value
+    /// and the shortest such path as a witness, in order. Wildcards match any chars, as [GlobMatcher] ones do
+    ///
+    /// [GlobMatcher]: crate::glob::GlobMatcher
+    pub fn overlapping(&self, acl: &Acl) -> Vec<(Acl, &V, String)> {
+        self.overlapping_with(acl, None)
+    }
+
+    /// Like [Trie::overlapping], wildcards staying within `separator` delimited segments as
+    /// [PathGlobMatcher] ones do
+    ///
+    /// [PathGlobMatcher]: crate::glob::PathGlobMatcher
+    pub fn overlapping_segments(&self, acl: &Acl, separator: char) -> Vec<(Acl, &V, String)> {
+        self.overlapping_with(acl, Some(separator))
+    }
+
+    fn overlapping_with(&self, acl: &Acl, separator: Option<char>) -> Vec<(Acl, &V, String)> {
+        let pattern = self.fold(acl).compiled();
+        self.iter()
+            .filter_map(|(key, value)| {
+                intersection(&key.compiled(), &pattern, separator).map(|witness| (key, value, witness))
+            })
+            .collect()
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
     use crate::key::TrieKey;
     use crate::matcher::{Event, PushdownStateMachine};
-    use crate::glob::GlobMatcher;
+    use crate::glob::{GlobMatcher, PathGlobMatcher};
     use crate::glob::acl::*;
 
 
     #[test]
+    fn glob_syntax_test() {
+        let compiled = TrieKey::new(Acl::new("a?[!0-9]{json,yaml}\\*")).seq;
+        let match_types: Vec<MatchType> = compiled.iter().map(|token| token.match_type.clone()).collect();
+        assert_eq!(vec![
+            MatchType::Literal,
+            MatchType::AnyChar,
+            MatchType::Class(CharClass { negated: true, ranges: vec![('0', '9')] }),
+            MatchType::Alternation(vec![vec!['j', 's', 'o', 'n'], vec!['y', 'a', 'm', 'l']]),
+        ], match_types);
+        assert_eq!(vec!['*'], compiled.last().unwrap().sequence);
+
+        // Unterminated constructs are literals
+        let compiled = TrieKey::new(Acl::new("[a-{b")).seq;
+        assert_eq!(1, compiled.len());
+        assert_eq!(MatchType::Literal, compiled[0].match_type);
+
+        let mut trie = AclTrie::new();
+        trie.insert(Acl::new("/files/*.{json,yaml}"), Permissions::READ);
+        trie.insert(Acl::new("/files/report-????"), Permissions::WRITE);
+        trie.insert(Acl::new("/files/[a-c]*"), Permissions::CREATE);
+        trie.insert(Acl::new("/files/[a-f]*"), Permissions::DELETE);
+        trie.insert(Acl::new("/files/[!a-z]*"), Permissions::WATCH);
+        trie.insert(Acl::new("/files/\\*"), Permissions::OWNER);
+
+        let get = |trie: &AclTrie, path: &str| trie.get_merge::<GlobMatcher>(&Acl::new(path)).unwrap_or_default();
+        assert_eq!(Permissions::READ | Permissions::CREATE | Permissions::DELETE, get(&trie, "/files/a.json"));
+        assert_eq!(Permissions::READ | Permissions::DELETE, get(&trie, "/files/d.yaml"));
+        assert_eq!(Permissions::empty(), get(&trie, "/files/x.yml"));
+        assert_eq!(Permissions::WRITE, get(&trie, "/files/report-2024"));
+        assert_eq!(Permissions::empty(), get(&trie, "/files/report-202"));
+        assert_eq!(Permissions::WATCH, get(&trie, "/files/0.txt"));
+        assert_eq!(Permissions::OWNER | Permissions::WATCH, get(&trie, "/files/*"));
+        assert_eq!(Permissions::WATCH, get(&trie, "/files/*x"));
+
+        // Tokens are never split across nodes
+        for (key, value) in &trie {
+            assert_eq!(Some(value), trie.get_exact(&key));
+        }
+        assert!(!trie.contains_key(&Acl::new("/files/[a-")));
+        assert_eq!(Some(Permissions::DELETE), trie.remove(&Acl::new("/files/[a-f]*")));
+        assert_eq!(Permissions::READ | Permissions::CREATE, get(&trie, "/files/a.json"));
+        assert_eq!(Permissions::empty(), get(&trie, "/files/d"));
+    }
+
+    #[test]
+    fn path_glob_test() {
+        let mut trie = AclTrie::new();
+        trie.insert(Acl::new("/path/*"), Permissions::READ);
+        trie.insert(Acl::new("/path/**"), Permissions::WRITE);
+        trie.insert(Acl::new("/path/**/index.?s"), Permissions::CREATE);
+        trie.insert(Acl::new("/path/[!_]*/*"), Permissions::DELETE);
+
+        let get = |trie: &AclTrie, path: &str| trie.get_merge::<PathGlobMatcher>(&Acl::new(path)).unwrap_or_default();
+        assert_eq!(Permissions::READ | Permissions::WRITE, get(&trie, "/path/other"));
+        assert_eq!(Permissions::WRITE | Permissions::DELETE, get(&trie, "/path/to/resource"));
+        assert_eq!(Permissions::WRITE, get(&trie, "/path/to/deep/resource"));
+        assert_eq!(Permissions::READ | Permissions::WRITE | Permissions::CREATE, get(&trie, "/path/index.js"));
+        assert_eq!(Permissions::WRITE | Permissions::CREATE, get(&trie, "/path/a/b/index.ts"));
+        assert_eq!(Permissions::WRITE, get(&trie, "/path/a/b/index./s"));
+        assert_eq!(Permissions::WRITE, get(&trie, "/path/_a/b"));
+        assert_eq!(Permissions::empty(), get(&trie, "/other/path"));
+
+        // `**` and its separator are never split
+        let compiled = TrieKey::new(Acl::new("/path/**/index")).seq;
+        assert_eq!(MatchType::DeepAnyOr, compiled[1].match_type);
+        assert_eq!(vec!['/', 'i', 'n', 'd', 'e', 'x'], compiled[1].sequence);
+        for (key, value) in &trie {
+            assert_eq!(Some(value), trie.get_exact(&key));
+        }
+        // `**` shares its first star with `*` tokens, but no boundary
+        let mut stars = AclTrie::new();
+        stars.insert(Acl::new("**"), Permissions::READ);
+        stars.insert(Acl::new("*\\"), Permissions::WRITE);
+        stars.insert(Acl::new("*"), Permissions::CREATE);
+        assert_eq!(Some(&Permissions::READ), stars.get_exact(&Acl::new("**")));
+        assert_eq!(Some(&Permissions::WRITE), stars.get_exact(&Acl::new("*\\")));
+        assert_eq!(Some(&Permissions::CREATE), stars.get_exact(&Acl::new("*")));
+
+        // Deep wildcards capture whole segments
+        let query = Acl::new("/path/a/b/index.js");
+        let (key, _, captures) = trie.get_with_captures::<PathGlobMatcher>(&query).unwrap();
+        assert_eq!(Acl::new("/path/**"), key);
+        assert_eq!("a/b/index.js", captures[0].slice(&query.path));
+        let mut matches = trie.matches::<PathGlobMatcher>(&query);
+        assert_eq!(Some(Acl::new("/path/**/index.?s")), matches.nth(1).map(|(key, _)| key));
+        assert_eq!("a/b", matches.captures()[0].slice(&query.path));
+
+        // Any char goes with the plain glob matcher
+        assert_eq!(Some(Permissions::READ | Permissions::WRITE | Permissions::DELETE),
+                   trie.get_merge::<GlobMatcher>(&Acl::new("/path/to/deep/resource")));
+        // Separators are configurable
+        let mut trie = AclTrie::new();
+        trie.insert(Acl::new("a.*"), Permissions::READ);
+        assert_eq!(Some(Permissions::READ), trie.get_merge::<PathGlobMatcher<'.'>>(&Acl::new("a.b")));
+        assert_eq!(None, trie.get_merge::<PathGlobMatcher<'.'>>(&Acl::new("a.b.c")));
+    }
+
+    #[test]
+    fn overlapping_test() {
+        let mut trie = AclTrie::new();
+        for path in ["/a/b*", "/a/*/y", "/a/c", "/a/**", "/b/[0-9]*", "/b/x\\*", "/a/{json,yaml}"] {
+            trie.insert(Acl::new(path), Permissions::READ);
+        }
+        let overlapping = |trie: &AclTrie, path: &str| trie.overlapping(&Acl::new(path))
+            .into_iter()
+            .map(|(key, _, witness)| (key.path, witness))
+            .collect::<Vec<_>>();
+        let expected = |pairs: &[(&str, &str)]| pairs.iter().map(|(key, witness)| (key.to_string(), witness.to_string())).collect::<Vec<_>>();
+        // The shortest witness may hold an empty segment
+        assert_eq!(expected(&[("/a/**", "/a//x"), ("/a/b*", "/a/b/x")]), overlapping(&trie, "/a/*/x"));
+        assert_eq!(expected(&[("/b/[0-9]*", "/b/0")]), overlapping(&trie, "/b/?"));
+        assert_eq!(expected(&[("/b/x\\*", "/b/x*")]), overlapping(&trie, "/b/x[*]"));
+        assert_eq!(expected(&[("/a/**", "/a/y"), ("/a/*/y", "/a/y/y"), ("/a/{json,yaml}", "/a/yaml")]), overlapping(&trie, "/a/y*"));
+        assert!(overlapping(&trie, "/c/*").is_empty());
+
+        // Witnesses are matched by both patterns
+        for (key, _, witness) in trie.overlapping(&Acl::new("/?/*")) {
+            let mut both = AclTrie::new();
+            both.insert(key.clone(), Permissions::READ);
+            both.insert(Acl::new("/?/*"), Permissions::WRITE);
+            assert_eq!(Some(Permissions::READ | Permissions::WRITE), both.get_merge::<GlobMatcher>(witness.as_str()), "{}", key);
+        }
+
+        // Segment aware wildcards
+        let overlapping = |trie: &AclTrie, path: &str| trie.overlapping_segments(&Acl::new(path), '/')
+            .into_iter()
+            .map(|(key, _, witness)| (key.path, witness))
+            .collect::<Vec<_>>();
+        assert_eq!(expected(&[("/a/**", "/a//a"), ("/a/*/y", "/a//y")]), overlapping(&trie, "/a/*/?"));
+        assert_eq!(expected(&[("/a/**", "/a/b/x")]), overlapping(&trie, "/a/b/x"));
+    }
+
+    #[test]
+    #[allow(clippy::useless_vec)]
     fn acl_trie_test() {
         {
             let token1 = String::from("f20000000001XXXG");
@@ -1,254 +1,305 @@
 //! A tiny and limited glob matcher implementation for FR Tries (optional)
 pub mod acl;
 
-use std::cell::RefCell;
 use std::sync::Arc;
-use crate::matcher::{Ahead, Event, MatchType, PushdownStateMachine, State, StateSequence};
+use crate::matcher::{Capture, CharClass, Event, MatchType, PushdownStateMachine, RegexNode, State, StateSequence};
+use crate::nfa::{self, Inst, Vm};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct MachineInstance {
-    tokens: Vec<Arc<StateSequence>>,
+    vm: Vm,
     state: State,
-    glob_idx: usize,
-    glob_char_idx: usize,
+    end_of_stream: bool,
+    /// Segment separator which single char wildcards never cross, if any
+    separator: Option<char>,
 }
 
 impl MachineInstance {
 
+    #[inline]
+    fn new(separator: Option<char>) -> Self {
+        Self {
+            vm: Vm::new(),
+            state: State::Expecting,
+            end_of_stream: false,
+            separator,
+        }
+    }
+
+    /// Appends the glob tokens of a trie node to the program
+    #[inline]
+    #[allow(clippy::ptr_arg)]
+    fn step_in(&mut self, tokens: &Vec<Arc<StateSequence>>) {
+        self.vm.extend(Self::program(tokens, self.vm.len(), self.separator));
+        self.update_state();
+    }
+
+    /// Translates glob tokens into instructions placed at `base`
+    fn program(tokens: &[Arc<StateSequence>], base: usize, separator: Option<char>) -> Vec<Inst> {
+        let mut program = Vec::new();
+        for token in tokens {
+            Self::compile(token, base, separator, &mut program);
+        }
+        program
+    }
+
     #[inline]
     fn feed(&mut self, ev: Event) {
         match (&self.state, ev) {
             (State::Accepting | State::Expecting, Event::EndOfStream) => {
-                if self.at_end() {
-                    match &self.state {
-                        State::Accepting => {
-                            self.state = State::Accepted;
-                        }
-                        State::Expecting => {
-                            self.state = State::Accepted;
-                        }
-                        State::Accepted => {
-                            self.state = State::Accepted;
-                        }
-                        State::Rejected => {
-                            self.state = State::Rejected;
-                        }
-                        State::Beyond => {
-                            self.state = State::Beyond;
-                        }
-                        State::Failure(msg) => {
-                            self.state = State::Failure(msg.clone());
-                        }
-                    }
-                }
-                else {
-                    match self.look_ahead() {
-                        None => {
-                            self.state = State::Accepted;
-                        }
-                        Some(_ahead) => {
-                            self.state = State::Rejected;
-                        }
-                    }
-
-                }
-            },
-            (State::Accepting | State::Expecting, Event::CharIn(ch)) => {
-                match self.look_ahead() {
-                    None => {
-                        self.state = State::Beyond;
-                    }
-                    Some(ahead) => {
-                        match ahead {
-                            Ahead::Exactly(current_char) => {
-                                if current_char == ch {
-                                    self.advance();
-                                    if self.at_end() {
-                                        //self.state = State::Accepted;
-                                    }
-                                    else {
-                                        //self.state = State::Expecting;
-                                    }
-                                }
-                                else if ch < current_char {
-                                    self.state = State::Beyond;
-                                }
-                                else {
-                                    self.state = State::Rejected;
-                                }
-                            }
-                            Ahead::AnyOr(current_char) => {
-                                if current_char == ch {
-                                    self.advance();
-                                    if self.at_end() {
-                                        //self.state = State::Accepted;
-                                    }
-                                    self.state = State::Accepting;
-                                }
-                            }
-                            Ahead::Any => {
-                                self.state = State::Accepting;
-                            }
-                        }
-                    }
-                }
+                self.end_of_stream = true;
+                self.vm.finish();
+                self.update_state();
+            }
+            (State::Accepting | State::Expecting, Event::CharIn(ch)) if !self.end_of_stream => {
+                self.vm.feed(ch);
+                self.update_state();
             }
             (s, e) => {
-                self.state = State::Failure(format!("Wrong state, event combination: {:#?} {:#?}", s, e)
-                    .to_string())
+                self.state = State::Failure(format!("Wrong state, event combination: {:#?} {:#?}", s, e))
             }
         }
     }
 
     #[inline]
-    fn look_ahead(&self) -> Option<Ahead> {
-        match self.tokens.get(self.glob_idx) {
-            None => {
-                return None; // Exceeding limits
+    fn update_state(&mut self) {
+        self.state = if self.vm.accepted().is_some() {
+            if self.end_of_stream {
+                State::Accepted
             }
-            Some(current_glob) => {
-                match current_glob.sequence.get(self.glob_char_idx) {
-                    None => {
-                        match current_glob.match_type {
-                            MatchType::Literal => {
-                                return None;
-                            }
-                            MatchType::AnyOr => {
-                                return Some(Ahead::Any)
-                            }
-                        }
-                    }
-                    Some(current_char) => {
-                        match current_glob.match_type {
-                            MatchType::Literal => {
-                                return Some(Ahead::Exactly(*current_char))
-                            }
-                            MatchType::AnyOr => {
-                                return Some(Ahead::AnyOr(*current_char))
-                            }
-                        }
-                    }
-                }
+            else {
+                State::Accepting
             }
         }
+        else if self.vm.is_alive() {
+            // Either waiting for more chars or for the tokens of deeper nodes
+            State::Expecting
+        }
+        else {
+            State::Rejected
+        };
     }
 
     #[inline]
-    fn advance(&mut self) {
-        match self.tokens.get(self.glob_idx) {
-            None => {
-                panic!("Cannot advance");
+    fn accepts_more(&self) -> bool {
+        !self.end_of_stream && self.vm.accepts_more()
+    }
+
+    /// Translates a glob token into instructions placed at `base`.
+    /// With a `separator`, `*`, `?` and negated classes stay within a segment, while `**` crosses
+    /// segments, `**/` matching zero or more whole segments.
+    fn compile(token: &StateSequence, base: usize, separator: Option<char>, program: &mut Vec<Inst>) {
+        let any = separator.map_or(Inst::Any, Inst::AnyBut);
+        let mut sequence = token.sequence.as_slice();
+        match token.match_type {
+            MatchType::Literal => {}
+            MatchType::AnyOr => {
+                Self::compile_any_or(any, base, program);
             }
-            Some(current_glob) => {
-                if self.glob_char_idx < current_glob.sequence.len() - 1 {
-                    self.glob_char_idx += 1;
+            MatchType::DeepAnyOr => match separator {
+                Some(separator) if sequence.first() == Some(&separator) => {
+                    // Either some segments, separator included, or none at all
+                    let start = base + program.len();
+                    program.push(Inst::Open);
+                    program.push(Inst::Split(start + 2, start + 8));
+                    program.push(Inst::Split(start + 3, start + 5));
+                    program.push(Inst::Any);
+                    program.push(Inst::Jump(start + 2));
+                    program.push(Inst::Close);
+                    program.push(Inst::Char(separator));
+                    program.push(Inst::Jump(start + 9));
+                    program.push(Inst::Close);
+                    sequence = &sequence[1..];
+                }
+                _ => {
+                    Self::compile_any_or(Inst::Any, base, program);
                 }
-                else if self.glob_idx < self.tokens.len() - 1 {
-                    self.glob_char_idx = 0;
-                    self.glob_idx += 1;
+            },
+            MatchType::AnyLevel { separator, excluded } => {
+                let first = Self::excluding(&[Some(separator), excluded]);
+                Self::compile_repeat(first, Inst::AnyBut(separator), base, program);
+            }
+            MatchType::AnyLevels { separator, excluded } => {
+                let first = Self::excluding(&[excluded]);
+                if sequence.first() == Some(&separator) {
+                    // Either some levels, separator included, or none at all
+                    let start = base + program.len();
+                    program.push(Inst::Split(start + 1, start + 10));
+                    Self::compile_repeat(first, Inst::Any, base, program);
+                    program.push(Inst::Char(separator));
+                    program.push(Inst::Jump(start + 12));
+                    program.push(Inst::Open);
+                    program.push(Inst::Close);
+                    sequence = &sequence[1..];
                 }
-                else { // Position at END
-                    self.glob_char_idx = 0;
-                    self.glob_idx = self.tokens.len();
+                else {
+                    Self::compile_repeat(first, Inst::Any, base, program);
                 }
             }
-        }
-    }
-
-    #[inline]
-    fn at_end(&self) -> bool {
-        match self.tokens.get(self.glob_idx) {
-            None => {
-                true
+            MatchType::SubLevels { separator } => {
+                let start = base + program.len();
+                program.push(Inst::Split(start + 1, start + 8));
+                program.push(Inst::Char(separator));
+                Self::compile_any_or(Inst::Any, base, program);
+                program.push(Inst::Jump(start + 10));
+                program.push(Inst::Open);
+                program.push(Inst::Close);
+            }
+            MatchType::Regex(ref node) => {
+                Self::compile_regex(node, base, program);
+            }
+            MatchType::AnyChar => {
+                program.push(any);
             }
-            Some(current_glob) => {
-                if self.glob_idx + 1 >= self.tokens.len() {
-                    if self.glob_char_idx + 1 > current_glob.sequence.len() {
-                        return true;
+            MatchType::Class(ref class) => {
+                let mut class = class.clone();
+                if let (true, Some(separator)) = (class.negated, separator) {
+                    class.ranges.push((separator, separator));
+                }
+                program.push(Inst::Class(class));
+            }
+            MatchType::Alternation(ref branches) => {
+                // Chain of splits, each branch jumping to the end once matched
+                let end = base + program.len()
+                    + branches.iter().map(|branch| branch.len() + 2).sum::<usize>() - 2;
+                for (index, branch) in branches.iter().enumerate() {
+                    let last = index + 1 == branches.len();
+                    if !last {
+                        let start = base + program.len();
+                        program.push(Inst::Split(start + 1, start + branch.len() + 2));
                     }
-                    else {
-                        return false;
+                    for ch in branch.iter() {
+                        program.push(Inst::Char(*ch));
+                    }
+                    if !last {
+                        program.push(Inst::Jump(end));
                     }
-                }
-                else {
-                    return false;
                 }
             }
         }
+        for ch in sequence.iter() {
+            program.push(Inst::Char(*ch));
+        }
     }
 
-    #[inline]
-    fn is_expecting(&self) -> bool {
-        match self.tokens.get(self.glob_idx) {
-            None => {
-                false
+    /// Thompson construction of a regex, jump targets being patched once known
+    fn compile_regex(node: &RegexNode, base: usize, program: &mut Vec<Inst>) {
+        match node {
+            RegexNode::Char(ch) => program.push(Inst::Char(*ch)),
+            RegexNode::Any => program.push(Inst::Any),
+            RegexNode::Class(class) => program.push(Inst::Class(class.clone())),
+            RegexNode::Group(branches) => {
+                let mut jumps = Vec::new();
+                for (index, branch) in branches.iter().enumerate() {
+                    let last = index + 1 == branches.len();
+                    let split = program.len();
+                    if !last {
+                        program.push(Inst::Split(0, 0));
+                    }
+                    for node in branch {
+                        Self::compile_regex(node, base, program);
+                    }
+                    if !last {
+                        jumps.push(program.len());
+                        program.push(Inst::Jump(0));
+                        program[split] = Inst::Split(base + split + 1, base + program.len());
+                    }
+                }
+                for jump in jumps {
+                    program[jump] = Inst::Jump(base + program.len());
+                }
             }
-            Some(_) => {
-                if self.glob_idx == self.tokens.len() {
-                    return false;
+            RegexNode::Repeat { node, min, max } => {
+                for _ in 0..*min {
+                    Self::compile_regex(node, base, program);
                 }
-                else {
-                    return true;
+                match max {
+                    None => {
+                        let split = program.len();
+                        program.push(Inst::Split(0, 0));
+                        Self::compile_regex(node, base, program);
+                        program.push(Inst::Jump(base + split));
+                        program[split] = Inst::Split(base + split + 1, base + program.len());
+                    }
+                    Some(max) => {
+                        let mut splits = Vec::new();
+                        for _ in *min..*max {
+                            splits.push(program.len());
+                            program.push(Inst::Split(0, 0));
+                            Self::compile_regex(node, base, program);
+                        }
+                        for split in splits {
+                            program[split] = Inst::Split(base + split + 1, base + program.len());
+                        }
+                    }
                 }
             }
         }
     }
+
+    /// Greedy, capturing and optional `first` followed by any repetition of `any`
+    fn compile_repeat(first: Inst, any: Inst, base: usize, program: &mut Vec<Inst>) {
+        let start = base + program.len();
+        program.push(Inst::Open);
+        program.push(Inst::Split(start + 2, start + 6));
+        program.push(first);
+        program.push(Inst::Split(start + 4, start + 6));
+        program.push(any);
+        program.push(Inst::Jump(start + 3));
+        program.push(Inst::Close);
+    }
+
+    /// Consumes any char but the given ones
+    fn excluding(excluded: &[Option<char>]) -> Inst {
+        let ranges: Vec<(char, char)> = excluded.iter().flatten().map(|ch| (*ch, *ch)).collect();
+        match ranges.as_slice() {
+            [] => Inst::Any,
+            [(ch, _)] => Inst::AnyBut(*ch),
+            _ => Inst::Class(CharClass { negated: true, ranges }),
+        }
+    }
+
+    /// Greedy, capturing repetition of `any`
+    fn compile_any_or(any: Inst, base: usize, program: &mut Vec<Inst>) {
+        let start = base + program.len();
+        program.push(Inst::Open);
+        program.push(Inst::Split(start + 2, start + 4));
+        program.push(any);
+        program.push(Inst::Jump(start + 1));
+        program.push(Inst::Close);
+    }
+}
+
+/// The shortest string matched by both glob patterns, if any. With a `separator`, wildcards match as
+/// [PathGlobMatcher] ones do, and as [GlobMatcher] ones otherwise
+pub fn intersection(first: &[Arc<StateSequence>], second: &[Arc<StateSequence>], separator: Option<char>) -> Option<String> {
+    nfa::intersection(&MachineInstance::program(first, 0, separator), &MachineInstance::program(second, 0, separator))
 }
 
 /////////////////////////////
+/// Glob matcher whose wildcards match any chars
 #[derive(Debug, Clone)]
 pub struct GlobMatcher {
-    stack: Vec<Arc<RefCell<MachineInstance>>>,
+    stack: Vec<MachineInstance>,
+    separator: Option<char>,
 }
 
 impl PushdownStateMachine for GlobMatcher {
     fn new() -> Self {
         Self {
             stack: Vec::new(),
+            separator: None,
         }
     }
 
     #[inline]
     fn step_in(&mut self, sequence: &Vec<Arc<StateSequence>>) {
-        let new_instance = match self.stack.last() {
-            None => {
-                let initial_state = match sequence.first() {
-                    None => {
-                        State::Failure(String::from("No regexp provided"))
-                    }
-                    Some(first_seq) => {
-                        match first_seq.match_type {
-                            MatchType::Literal => {
-                                State::Expecting
-                            },
-                            MatchType::AnyOr => {
-                                State::Accepting
-                            },
-                        }
-                    }
-                };
-
-                Arc::new(RefCell::new(MachineInstance {
-                    tokens: sequence.clone(),
-                    state: initial_state,
-                    glob_idx: 0,
-                    glob_char_idx: 0,
-                }))
-            }
-            Some(top) => {
-                let machine = top.borrow();
-                let mut tokens = machine.tokens.clone();
-                for aditional_token in sequence {
-                    tokens.push(aditional_token.clone());
-                }
-                Arc::new(RefCell::new(MachineInstance {
-                    tokens,
-                    state: machine.state.clone(),
-                    glob_idx: machine.glob_idx,
-                    glob_char_idx: machine.glob_char_idx,
-                }))
-            }
+        // An empty key is a valid pattern, only accepting the empty match key
+        let mut new_instance = match self.stack.last() {
+            None => MachineInstance::new(self.separator),
+            Some(top) => top.clone(),
         };
+        new_instance.step_in(sequence);
         self.stack.push(new_instance);
     }
 
@@ -260,23 +311,22 @@ impl PushdownStateMachine for GlobMatcher {
     #[inline]
     fn accepts_more(&self) -> bool {
         match self.stack.last() {
-            Some(machine) => machine.borrow().is_expecting(),
+            Some(machine) => machine.accepts_more(),
             None => false
         }
     }
 
     #[inline]
     fn feed(&mut self, ev: Event) {
-        match self.stack.last_mut() {
-            Some(machine) => machine.borrow_mut().feed(ev),
-            None => {}
+        if let Some(machine) = self.stack.last_mut() {
+            machine.feed(ev)
         }
     }
 
     #[inline]
     fn state(&self) -> State {
         match self.stack.last() {
-            Some(machine) => machine.borrow().state.clone(),
+            Some(machine) => machine.state.clone(),
             None => State::Failure(String::from("Machine not initiallized"))
         }
     }
@@ -284,7 +334,7 @@ impl PushdownStateMachine for GlobMatcher {
     #[inline]
     fn is_sink(&self) -> bool {
         match self.stack.last() {
-            Some(machine) => match machine.borrow().state {
+            Some(machine) => match machine.state {
                 State::Accepting => false,
                 State::Expecting => false,
                 State::Accepted => true,
@@ -295,6 +345,68 @@ impl PushdownStateMachine for GlobMatcher {
             None => true,
         }
     }
+
+    #[inline]
+    fn captures(&self) -> Vec<Capture> {
+        match self.stack.last() {
+            Some(machine) => machine.vm.accepted().map(|captures| captures.to_vec()).unwrap_or_default(),
+            None => Vec::new()
+        }
+    }
+}
+
+/////////////////////////////
+/// Segment aware glob matcher: `*`, `?` and negated classes never cross `SEPARATOR`, while `**`
+/// matches any number of segments
+#[derive(Debug, Clone)]
+pub struct PathGlobMatcher<const SEPARATOR: char = '/'> {
+    inner: GlobMatcher,
+}
+
+impl<const SEPARATOR: char> PushdownStateMachine for PathGlobMatcher<SEPARATOR> {
+    fn new() -> Self {
+        Self {
+            inner: GlobMatcher {
+                stack: Vec::new(),
+                separator: Some(SEPARATOR),
+            },
+        }
+    }
+
+    #[inline]
+    fn step_in(&mut self, sequence: &Vec<Arc<StateSequence>>) {
+        self.inner.step_in(sequence)
+    }
+
+    #[inline]
+    fn step_out(&mut self) {
+        self.inner.step_out()
+    }
+
+    #[inline]
+    fn accepts_more(&self) -> bool {
+        self.inner.accepts_more()
+    }
+
+    #[inline]
+    fn feed(&mut self, ev: Event) {
+        self.inner.feed(ev)
+    }
+
+    #[inline]
+    fn state(&self) -> State {
+        self.inner.state()
+    }
+
+    #[inline]
+    fn is_sink(&self) -> bool {
+        self.inner.is_sink()
+    }
+
+    #[inline]
+    fn captures(&self) -> Vec<Capture> {
+        self.inner.captures()
+    }
 }
 
 #[cfg(test)]
@@ -327,8 +439,8 @@ mod tests {
 
         println!("{:?}", matcher.stack.last().unwrap());
         let mi = matcher.stack.last().unwrap();
-        let _v = mi.as_ref().borrow().look_ahead();
-        let _v = mi.as_ref().borrow().is_expecting();
+        let _v = mi.state.clone();
+        let _v = mi.accepts_more();
 
         matcher.step_out();
         let _st = matcher.state();
@@ -351,8 +463,53 @@ mod tests {
         let _st = matcher.accepts_more();
 
         let mi = matcher.stack.last().unwrap();
-        let _v = mi.as_ref().borrow().look_ahead();
-        let _v = mi.as_ref().borrow().is_expecting();
+        let _v = mi.state.clone();
+        let _v = mi.accepts_more();
+
+    }
+
+    fn run(pattern: &[Vec<Arc<StateSequence>>], input: &str) -> (State, Vec<Capture>) {
+        let mut matcher = GlobMatcher::new();
+        for tokens in pattern {
+            matcher.step_in(tokens);
+        }
+        for ch in input.chars() {
+            matcher.feed(Event::CharIn(ch));
+        }
+        matcher.feed(Event::EndOfStream);
+        (matcher.state(), matcher.captures())
+    }
+
+    #[test]
+    fn captures_test() {
+        let literal = |s: &str| Arc::new(StateSequence { match_type: MatchType::Literal, sequence: s.chars().collect() });
+        let any_or = |s: &str| Arc::new(StateSequence { match_type: MatchType::AnyOr, sequence: s.chars().collect() });
+
+        let pattern = vec![vec![literal("/users/"), any_or("/profile")]];
+        let (state, captures) = run(&pattern, "/users/42/profile");
+        assert_eq!(State::Accepted, state);
+        assert_eq!(vec![Capture { start: 7, end: 9 }], captures);
+        assert_eq!("42", captures[0].slice("/users/42/profile"));
+
+        // Not accepted, though deeper tokens could still match the remaining `/x`
+        let (state, captures) = run(&pattern, "/users/42/profile/x");
+        assert_eq!(State::Expecting, state);
+        assert!(captures.is_empty());
+        let (state, _) = run(&pattern, "/users/42/prof");
+        assert_eq!(State::Rejected, state);
+
+        // Wildcards are greedy, and may capture nothing
+        let pattern = vec![vec![any_or("."), any_or("")]];
+        let (state, captures) = run(&pattern, "a.b.c");
+        assert_eq!(State::Accepted, state);
+        assert_eq!(vec![Capture { start: 0, end: 3 }, Capture { start: 4, end: 5 }], captures);
+        let (_, captures) = run(&pattern, ".");
+        assert_eq!(vec![Capture { start: 0, end: 0 }, Capture { start: 1, end: 1 }], captures);
 
+        // Patterns split across trie nodes
+        let pattern = vec![vec![literal("/users/"), any_or("")], vec![literal("/pro")], vec![literal("file")]];
+        let (state, captures) = run(&pattern, "/users/ñu/profile");
+        assert_eq!(State::Accepted, state);
+        assert_eq!("ñu", captures[0].slice("/users/ñu/profile"));
     }
 }
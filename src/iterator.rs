@@ -1,118 +1,694 @@
 //! The Trie iterator based on a pushdown automata to perform lookup
 
+use crate::fold::Folding;
 use crate::key::KeyPrefix;
-use crate::matcher::{Event, PushdownStateMachine, State};
+use crate::matcher::{Capture, Event, PushdownStateMachine, State};
 use crate::node::RFRNode;
+use std::iter::Peekable;
+use std::cmp::Ordering;
+use std::ops::Bound;
+use std::{slice, vec};
 
-///! Tracks lookup
-struct LookupState<'a, K: 'a + KeyPrefix + Clone, V: 'a + Clone> {
+/// Tracks lookup
+struct LookupState<'a, K: 'a + KeyPrefix + Clone, V: 'a + Clone, I: Iterator<Item = K::Symbol>> {
     node: &'a RFRNode<K, V>,
     current_child_idx: usize,
-    key_char_pos: usize,
+    /// The match key symbols left to feed at this level
+    match_key: Peekable<I>,
 }
 
-///! The iterator implementation
-pub struct TrieIterator<'a, K: 'a + KeyPrefix + Clone, V: 'a + Clone, M: PushdownStateMachine + Clone> {
-    stack: Vec<LookupState<'a, K, V>>,
-    match_key_chars: Vec<char>,
-    matcher_sm: M
+/// Lazy iterator over every stored key accepting a match key, as told by the matcher `M`.
+/// The match key symbols are streamed from `I`, cloned on every level to backtrack.
+/// Nodes are visited depth-first, and full keys only built for the matches yielded
+pub struct TrieIterator<'a, K, V, M, I>
+    where K: 'a + KeyPrefix + Clone, V: 'a + Clone, M: PushdownStateMachine<K::Symbol> + Clone, I: Iterator<Item = K::Symbol> + Clone
+{
+    stack: Vec<LookupState<'a, K, V, I>>,
+    matcher_sm: M,
+    captures: Vec<Capture>,
+    cost: usize,
+    /// The query of folding tries, along with the folding, so that captures index the query as given
+    unfolded: Option<(K, Folding)>,
+    /// Offsets in the query of each offset in its folded symbols, once needed
+    offsets: Option<Vec<usize>>,
 }
 
-impl <'a, K: KeyPrefix + Clone, V: Clone, M: PushdownStateMachine + Clone> TrieIterator<'a, K, V, M> {
+impl <'a, K, V, M, I> TrieIterator<'a, K, V, M, I>
+    where K: KeyPrefix + Clone, V: Clone, M: PushdownStateMachine<K::Symbol> + Clone, I: Iterator<Item = K::Symbol> + Clone
+{
 
-    ///! Creates a new iterator
-    pub fn new(root: &'a RFRNode<K, V>, match_key: &K) -> Self {
-        let it = Self {
+    /// Creates a new iterator
+    pub(crate) fn new(root: &'a RFRNode<K, V>, match_key: I) -> Self {
+        Self {
             stack: vec![LookupState {
                 node: root,
                 current_child_idx: 0,
-                key_char_pos: 0,
+                match_key: match_key.peekable(),
             }],
-            match_key_chars: match_key.key_chars(),
             matcher_sm: M::new(),
+            captures: Vec::new(),
+            cost: 0,
+            unfolded: None,
+            offsets: None,
+        }
+    }
+
+    /// Makes the captures index the `query` the match key symbols were folded from
+    pub(crate) fn unfolding(mut self, query: K, folding: Folding) -> Self {
+        self.unfolded = Some((query, folding));
+        self
+    }
+
+    /// Maps the captures back to the query the match key symbols were folded from, if any
+    fn unfold_captures(&mut self) {
+        let Some((query, folding)) = &self.unfolded else {
+            return;
         };
-        it
+        if self.captures.is_empty() {
+            return;
+        }
+        let offsets = self.offsets.get_or_insert_with(|| {
+            // Folding a longer query prefix never yields fewer symbols
+            let lens: Vec<usize> = (0..=query.key_len())
+                .map(|index| query.new_from_key_prefix(index).folded_query(folding).key_len())
+                .collect();
+            (0..=lens[lens.len() - 1]).map(|offset| lens.partition_point(|len| *len <= offset) - 1).collect()
+        });
+        let unfold = |offset: usize| offsets.get(offset).or(offsets.last()).copied().unwrap_or(offset);
+        for capture in self.captures.iter_mut() {
+            capture.start = unfold(capture.start);
+            capture.end = unfold(capture.end);
+        }
+    }
+
+    /// Spans of the match key consumed by the wildcards of the last yielded key, in the query as given
+    /// (before any trie folding)
+    #[inline]
+    pub fn captures(&self) -> &[Capture] {
+        &self.captures
+    }
+
+    /// Cost of the last yielded key, as told by the matcher
+    #[inline]
+    pub fn cost(&self) -> usize {
+        self.cost
+    }
+
+    /// Walks on to the next stored key accepting the match key, yielding its node and value along
+    /// with the number of levels leading to its parent. Keys are left for the caller to build
+    pub(crate) fn next_match(&mut self) -> Option<(&'a RFRNode<K, V>, &'a V, usize)> {
+        loop {
+            let ls = self.stack.pop()?; // None: No more work to do
+
+            if ls.current_child_idx >= ls.node.children.len() {
+                // No (more) children. Backtrack to the parent level
+                if !self.stack.is_empty() {
+                    self.matcher_sm.step_out();
+                }
+                continue;
+            }
+
+            let child = ls.node.children.get(ls.current_child_idx).unwrap();
+            self.matcher_sm.step_in(&child.node_key.seq);
+            let mut match_key = ls.match_key.clone();
+            while let Some(ch) = match_key.next_if(|_| !self.matcher_sm.is_sink()) {
+                self.matcher_sm.feed(Event::CharIn(ch));
+                if !self.matcher_sm.accepts_more() {
+                    break;
+                }
+            }
+            if match_key.peek().is_none() && !self.matcher_sm.is_sink() {
+                // Flush (be always greedy)
+                self.matcher_sm.feed(Event::EndOfStream);
+            }
+
+            let mut next_sibling = LookupState {
+                node: ls.node,
+                current_child_idx: ls.current_child_idx + 1,
+                match_key: ls.match_key,
+            };
+            match self.matcher_sm.state() {
+                State::Accepting | State::Expecting => {
+                    self.stack.push(next_sibling);
+                    self.stack.push(LookupState {
+                        node: child,
+                        current_child_idx: 0,
+                        match_key,
+                    });
+                }
+                State::Accepted => {
+                    let captures = self.matcher_sm.captures();
+                    let cost = self.matcher_sm.cost();
+                    self.stack.push(next_sibling);
+                    let depth = self.stack.len();
+                    if child.children.is_empty() {
+                        self.matcher_sm.step_out();
+                    }
+                    else {
+                        // Deeper keys may still accept (e.g. `a*` and `a*b`)
+                        self.stack.push(LookupState {
+                            node: child,
+                            current_child_idx: 0,
+                            match_key,
+                        });
+                    }
+                    if let Some(value) = &child.value {
+                        self.captures = captures;
+                        self.unfold_captures();
+                        self.cost = cost;
+                        return Some((child, value, depth));
+                    }
+                }
+                State::Rejected => {
+                    self.matcher_sm.step_out();
+                    self.stack.push(next_sibling);
+                }
+                State::Beyond => {
+                    // Won't find anything beyond at this level
+                    self.matcher_sm.step_out();
+                    next_sibling.current_child_idx = next_sibling.node.children.len();
+                    self.stack.push(next_sibling);
+                }
+                State::Failure(reason) => {
+                    panic!("Internal error: {}", reason);
+                }
+            }
+        }
     }
 }
 
-impl <'a, K: 'a + KeyPrefix + Clone, V: 'a + Clone, M: PushdownStateMachine + Clone> Iterator for TrieIterator<'a, K, V, M> {
-    type Item = V;
+impl <'a, K, V, M, I> Iterator for TrieIterator<'a, K, V, M, I>
+    where K: 'a + KeyPrefix + Clone, V: 'a + Clone, M: PushdownStateMachine<K::Symbol> + Clone, I: Iterator<Item = K::Symbol> + Clone
+{
+    type Item = (K, &'a V);
 
-    /// Consume iterator
+    /// Consume iterator, yielding the full stored keys accepting the match key along with their values
     fn next(&mut self) -> Option<Self::Item> {
-        loop {
-            match self.stack.pop() {
-                None => { // No more work to do
-                    return None;
+        let (node, value, depth) = self.next_match()?;
+        let key = self.stack[1..depth].iter()
+            .fold(K::empty(), |key, level| key.new_from_concat(&level.node.node_key.key))
+            .new_from_concat(&node.node_key.key);
+        Some((key, value))
+    }
+}
+
+/// A node walked in key order, along with the siblings it adopted
+pub(crate) struct Walked<N> {
+    node: N,
+    /// Number of leading node key symbols walked along with the adopting sibling
+    offset: usize,
+    /// Siblings whose keys go on after the rest of the node key, walked among the node children
+    adopted: Vec<Walked<N>>,
+}
+
+impl <N: Clone> Clone for Walked<N> {
+    fn clone(&self) -> Self {
+        Self {
+            node: self.node.clone(),
+            offset: self.offset,
+            adopted: self.adopted.clone(),
+        }
+    }
+}
+
+impl <N> Walked<N> {
+
+    #[inline]
+    fn new(node: N) -> Self {
+        Self {
+            node,
+            offset: 0,
+            adopted: Vec::new(),
+        }
+    }
+
+    /// The node key symbols left to walk
+    #[inline]
+    fn rest<'a, K: 'a + KeyPrefix + Clone, V: 'a + Clone>(&'a self) -> impl Iterator<Item = K::Symbol> + Clone + 'a where N: AsRef<RFRNode<K, V>> {
+        self.node.as_ref().node_key.key.symbols().skip(self.offset)
+    }
+
+    /// The full key of the node, given the full key of the node adopting it or else of its parent
+    #[inline]
+    fn key<K: KeyPrefix + Clone, V: Clone>(&self, prefix: &K) -> K where N: AsRef<RFRNode<K, V>> {
+        let node_key = &self.node.as_ref().node_key.key;
+        if self.offset == 0 {
+            prefix.new_from_concat(node_key)
+        }
+        else {
+            prefix.new_from_concat(&node_key.new_from_postfix(self.offset))
+        }
+    }
+}
+
+/// Whether the `key` symbols go on after all the `prefix` symbols
+#[inline]
+fn extends<I: Iterator<Item = S>, S: PartialEq>(prefix: I, mut key: I) -> bool {
+    prefix.into_iter().all(|symbol| key.next() == Some(symbol)) && key.next().is_some()
+}
+
+/// Children of a node, in key order.
+///
+/// Children are sorted, but pattern keys may extend the key of a sibling without sharing a boundary
+/// with it (e.g. `*` and `**`): such siblings sort among the descendants of the shorter one, which
+/// adopts them
+enum Siblings<P, N> {
+    /// No child extends its sibling (the usual case): children are walked as they are
+    Plain(P),
+    Grouped(vec::IntoIter<Walked<N>>),
+}
+
+impl <P: Clone, N: Clone> Clone for Siblings<P, N> {
+    fn clone(&self) -> Self {
+        match self {
+            Siblings::Plain(children) => Siblings::Plain(children.clone()),
+            Siblings::Grouped(walked) => Siblings::Grouped(walked.clone()),
+        }
+    }
+}
+
+impl <P: Iterator<Item = N>, N> Siblings<P, N> {
+
+    /// Orders the `children` of a node along with the siblings the node `adopted`
+    fn new<K, V>(children: P, adopted: Vec<Walked<N>>) -> Self
+        where K: KeyPrefix + Clone, V: Clone, P: AsRef<[Box<RFRNode<K, V>>]>, N: AsRef<RFRNode<K, V>>
+    {
+        let plain = adopted.is_empty() && children.as_ref().windows(2).all(|pair| {
+            let (first, second) = (&pair[0].node_key.key, &pair[1].node_key.key);
+            first.symbols().lt(second.symbols()) && !extends(first.symbols(), second.symbols())
+        });
+        if plain {
+            return Siblings::Plain(children);
+        }
+        let mut walked: Vec<Walked<N>> = children.map(Walked::new).chain(adopted).collect();
+        walked.sort_by(|first, second| first.rest::<K, V>().cmp(second.rest::<K, V>()));
+        let mut grouped: Vec<Walked<N>> = Vec::with_capacity(walked.len());
+        for mut item in walked {
+            if let Some(head) = grouped.last_mut() {
+                if extends(head.rest::<K, V>(), item.rest::<K, V>()) {
+                    item.offset += head.rest::<K, V>().count();
+                    head.adopted.push(item);
+                    continue;
                 }
-                Some(ls) => {
+            }
+            grouped.push(item);
+        }
+        Siblings::Grouped(grouped.into_iter())
+    }
+}
+
+impl <P: Iterator<Item = N>, N> Iterator for Siblings<P, N> {
+    type Item = Walked<N>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Siblings::Plain(children) => children.next().map(Walked::new),
+            Siblings::Grouped(walked) => walked.next(),
+        }
+    }
+}
+
+impl <P: DoubleEndedIterator<Item = N>, N> DoubleEndedIterator for Siblings<P, N> {
+
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        match self {
+            Siblings::Plain(children) => children.next_back().map(Walked::new),
+            Siblings::Grouped(walked) => walked.next_back(),
+        }
+    }
+}
+
+/// Children of a node walked by value
+type OwnedChildren<K, V> = Siblings<vec::IntoIter<Box<RFRNode<K, V>>>, Box<RFRNode<K, V>>>;
+
+/// Children of a node walked by reference
+type Children<'a, K, V> = Siblings<slice::Iter<'a, Box<RFRNode<K, V>>>, &'a Box<RFRNode<K, V>>>;
 
-                    if ls.current_child_idx >= ls.node.children.len() {
-                        // No (more) children. Give up at this level
-                        break;
+/// A child of a node walked by reference
+type WalkedChild<'a, K, V> = Walked<&'a Box<RFRNode<K, V>>>;
+
+/// Owning iterator over the stored keys (fully reconstructed) and values, in order
+pub struct IntoIter<K: KeyPrefix + Clone, V: Clone> {
+    /// Children left to walk, along with the full key of their parent
+    stack: Vec<(K, OwnedChildren<K, V>)>,
+    remaining: usize,
+}
+
+impl <K: KeyPrefix + Clone, V: Clone> IntoIter<K, V> {
+
+    /// Creates a new iterator consuming the `root` node holding `size` keys
+    pub(crate) fn new(root: RFRNode<K, V>, size: usize) -> Self {
+        Self {
+            stack: vec![(K::empty(), Siblings::new(root.children.into_iter(), Vec::new()))],
+            remaining: size,
+        }
+    }
+}
+
+impl <K: KeyPrefix + Clone, V: Clone> Iterator for IntoIter<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (prefix, children) = self.stack.last_mut()?;
+            match children.next() {
+                None => {
+                    self.stack.pop();
+                }
+                Some(walked) => {
+                    let key = walked.key(prefix);
+                    let child = *walked.node;
+                    self.stack.push((key.clone(), Siblings::new(child.children.into_iter(), walked.adopted)));
+                    if let Some(value) = child.value {
+                        self.remaining -= 1;
+                        return Some((key, value));
                     }
+                }
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl <K: KeyPrefix + Clone, V: Clone> ExactSizeIterator for IntoIter<K, V> {}
+
+/// Children of a node left to walk, along with the full key of the node
+type Level<'a, K, V> = (K, Children<'a, K, V>);
+
+/// Lazy depth-first iterator over the stored keys (fully reconstructed) and values, in order
+pub struct Iter<'a, K: 'a + KeyPrefix + Clone, V: 'a + Clone> {
+    stack: Vec<Level<'a, K, V>>,
+    remaining: usize,
+}
+
+impl <'a, K: KeyPrefix + Clone, V: Clone> Iter<'a, K, V> {
+
+    /// Creates a new iterator over the `root` node holding `size` keys
+    pub(crate) fn new(root: &'a RFRNode<K, V>, size: usize) -> Self {
+        Self {
+            stack: vec![(K::empty(), Siblings::new(root.children.iter(), Vec::new()))],
+            remaining: size,
+        }
+    }
+
+}
+
+impl <'a, K: 'a + KeyPrefix + Clone, V: 'a + Clone> Iterator for Iter<'a, K, V> {
+    type Item = (K, &'a V);
 
-                    let child = ls.node.children.get(ls.current_child_idx).unwrap();
-                    self.matcher_sm.step_in(&child.node_key.seq);
-                    let mut advanced = 0 as usize;
-                    for ch in self.match_key_chars[ls.key_char_pos..].iter() {
-                        if self.matcher_sm.is_sink() {
-                            break;
-                        }
-                        self.matcher_sm.feed(Event::CharIn(*ch));
-                        advanced += 1;
-                        if !self.matcher_sm.accepts_more() {
-                            break;
-                        }
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (prefix, children) = self.stack.last_mut()?;
+            match children.next() {
+                None => {
+                    self.stack.pop();
+                }
+                Some(walked) => {
+                    let key = walked.key(prefix);
+                    let child: &'a RFRNode<K, V> = walked.node;
+                    self.stack.push((key.clone(), Siblings::new(child.children.iter(), walked.adopted)));
+                    if let Some(value) = &child.value {
+                        self.remaining -= 1;
+                        return Some((key, value));
                     }
-                    if ls.key_char_pos + advanced == self.match_key_chars.len() {
-                        if !self.matcher_sm.is_sink() { // Flush (be always greedy)
-                            self.matcher_sm.feed(Event::EndOfStream);
-                        }
+                }
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl <'a, K: 'a + KeyPrefix + Clone, V: 'a + Clone> ExactSizeIterator for Iter<'a, K, V> {}
+
+/// Lazy depth-first iterator over the stored keys (fully reconstructed) starting with a prefix and
+/// their values, in order. Subtrees diverging from the prefix are pruned as they are reached, so
+/// keys are not counted up front
+pub struct IterPrefix<'a, K: 'a + KeyPrefix + Clone, V: 'a + Clone> {
+    /// Children left to walk, along with the full key of their parent and whether it starts with the prefix
+    stack: Vec<(K, Children<'a, K, V>, bool)>,
+    prefix: Vec<K::Symbol>,
+}
+
+impl <'a, K: KeyPrefix + Clone, V: Clone> IterPrefix<'a, K, V> {
+
+    /// Creates a new iterator over the keys of the `root` node starting with the `prefix` symbols
+    pub(crate) fn new(root: &'a RFRNode<K, V>, prefix: Vec<K::Symbol>) -> Self {
+        Self {
+            stack: vec![(K::empty(), Siblings::new(root.children.iter(), Vec::new()), prefix.is_empty())],
+            prefix,
+        }
+    }
+
+    /// Whether `key` starts with the prefix, or else leads up to it (`None` diverging from it)
+    fn starts_with_prefix(&self, key: &K) -> Option<bool> {
+        let mut symbols = key.symbols();
+        for symbol in self.prefix.iter() {
+            match symbols.next() {
+                None => return Some(false),
+                Some(key_symbol) if key_symbol == *symbol => {}
+                Some(_) => return None,
+            }
+        }
+        Some(true)
+    }
+}
+
+impl <'a, K: 'a + KeyPrefix + Clone, V: 'a + Clone> Iterator for IterPrefix<'a, K, V> {
+    type Item = (K, &'a V);
 
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (prefix, children, within) = self.stack.last_mut()?;
+            match children.next() {
+                None => {
+                    self.stack.pop();
+                }
+                Some(walked) => {
+                    let key = walked.key(prefix);
+                    let within = *within || match self.starts_with_prefix(&key) {
+                        Some(within) => within,
+                        None => continue,
+                    };
+                    let child: &'a RFRNode<K, V> = walked.node;
+                    self.stack.push((key.clone(), Siblings::new(child.children.iter(), walked.adopted), within));
+                    if let (true, Some(value)) = (within, &child.value) {
+                        return Some((key, value));
                     }
-                    match self.matcher_sm.state() {
-                        State::Accepting | State::Expecting => {
-                            self.stack.push(LookupState {
-                                node: child,
-                                current_child_idx: 0,
-                                key_char_pos: ls.key_char_pos + advanced
-                            });
-                        }
-                        State::Accepted => {
-                            self.matcher_sm.step_out();
-                            self.stack.push(LookupState {
-                                node: ls.node,
-                                current_child_idx: ls.current_child_idx + 1,
-                                key_char_pos: ls.key_char_pos
-                            });
-                            return match &child.value {
-                                None => {
-                                    None
-                                },
-                                Some(v) => {
-                                    Some(v.clone())
-                                }
-                            }
-                        }
-                        State::Rejected => {
-                            self.matcher_sm.step_out();
-                            self.stack.push(LookupState {
-                                node: ls.node,
-                                current_child_idx: ls.current_child_idx + 1,
-                                key_char_pos: ls.key_char_pos
-                            });
-                        }
-                        State::Beyond => {
-                            return None // Won't find anything beyond
-                        }
-                        State::Failure(reason) => {
-                            panic!("Internal error: {}", reason);
-                        }
+                }
+            }
+        }
+    }
+}
+
+/// Iterator over the stored keys, in order
+pub struct Keys<'a, K: 'a + KeyPrefix + Clone, V: 'a + Clone> {
+    pub(crate) inner: Iter<'a, K, V>,
+}
+
+impl <'a, K: 'a + KeyPrefix + Clone, V: 'a + Clone> Iterator for Keys<'a, K, V> {
+    type Item = K;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(key, _)| key)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl <'a, K: 'a + KeyPrefix + Clone, V: 'a + Clone> ExactSizeIterator for Keys<'a, K, V> {}
+
+/// Iterator over the stored values, in key order
+pub struct Values<'a, K: 'a + KeyPrefix + Clone, V: 'a + Clone> {
+    pub(crate) inner: Iter<'a, K, V>,
+}
+
+impl <'a, K: 'a + KeyPrefix + Clone, V: 'a + Clone> Iterator for Values<'a, K, V> {
+    type Item = &'a V;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(_, value)| value)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl <'a, K: 'a + KeyPrefix + Clone, V: 'a + Clone> ExactSizeIterator for Values<'a, K, V> {}
+
+/// Compares two keys symbol by symbol
+#[inline]
+pub(crate) fn cmp_keys<K: KeyPrefix>(first: &K, second: &K) -> Ordering {
+    first.symbols().cmp(second.symbols())
+}
+
+/// A node walked by a [Range], along with its full key
+struct RangeLevel<'a, K: 'a + KeyPrefix + Clone, V: 'a + Clone> {
+    key: K,
+    /// The node value, unless walked already
+    value: Option<&'a V>,
+    /// Children left to walk
+    children: Children<'a, K, V>,
+}
+
+impl <'a, K: 'a + KeyPrefix + Clone, V: 'a + Clone> RangeLevel<'a, K, V> {
+
+    #[inline]
+    fn new(node: &'a RFRNode<K, V>, key: K, adopted: Vec<WalkedChild<'a, K, V>>) -> Self {
+        Self {
+            key,
+            value: node.value.as_ref(),
+            children: Siblings::new(node.children.iter(), adopted),
+        }
+    }
+}
+
+/// Double-ended lazy iterator over the stored keys (fully reconstructed) and values within a range,
+/// in order. Subtrees whose keys all fall out of bounds are skipped
+pub struct Range<'a, K: 'a + KeyPrefix + Clone, V: 'a + Clone> {
+    front: Vec<RangeLevel<'a, K, V>>,
+    back: Vec<RangeLevel<'a, K, V>>,
+    bounds: (Bound<K>, Bound<K>),
+    /// Last keys yielded from each end, so that both ends never cross
+    front_key: Option<K>,
+    back_key: Option<K>,
+}
+
+impl <'a, K: KeyPrefix + Clone, V: Clone> Range<'a, K, V> {
+
+    /// Creates a new iterator over the keys of the `root` node within the `bounds`
+    pub(crate) fn new(root: &'a RFRNode<K, V>, bounds: (Bound<K>, Bound<K>)) -> Self {
+        Self {
+            front: vec![RangeLevel::new(root, K::empty(), Vec::new())],
+            back: vec![RangeLevel::new(root, K::empty(), Vec::new())],
+            bounds,
+            front_key: None,
+            back_key: None,
+        }
+    }
+
+    /// Whether the keys starting with `prefix` may fall within bounds
+    #[inline]
+    fn may_hold(&self, prefix: &K) -> bool {
+        let from_start = match &self.bounds.0 {
+            Bound::Included(start) | Bound::Excluded(start) => {
+                // Keys before the start bound may still lead to it
+                let mut start_symbols = start.symbols();
+                cmp_keys(prefix, start) != Ordering::Less || prefix.symbols().all(|symbol| start_symbols.next() == Some(symbol))
+            }
+            Bound::Unbounded => true,
+        };
+        let to_end = match &self.bounds.1 {
+            Bound::Included(end) => cmp_keys(prefix, end) != Ordering::Greater,
+            Bound::Excluded(end) => cmp_keys(prefix, end) == Ordering::Less,
+            Bound::Unbounded => true,
+        };
+        from_start && to_end
+    }
+
+    /// Whether `key` falls within bounds
+    #[inline]
+    fn contains(&self, key: &K) -> bool {
+        let from_start = match &self.bounds.0 {
+            Bound::Included(start) => cmp_keys(key, start) != Ordering::Less,
+            Bound::Excluded(start) => cmp_keys(key, start) == Ordering::Greater,
+            Bound::Unbounded => true,
+        };
+        let to_end = match &self.bounds.1 {
+            Bound::Included(end) => cmp_keys(key, end) != Ordering::Greater,
+            Bound::Excluded(end) => cmp_keys(key, end) == Ordering::Less,
+            Bound::Unbounded => true,
+        };
+        from_start && to_end
+    }
+
+    /// Descends into a `walked` child of the node walked last by the `levels` end, unless out of bounds
+    #[inline]
+    fn step_in(&self, levels: &[RangeLevel<'a, K, V>], walked: WalkedChild<'a, K, V>) -> Option<RangeLevel<'a, K, V>> {
+        let key = walked.key(&levels.last()?.key);
+        if !self.may_hold(&key) {
+            return None;
+        }
+        Some(RangeLevel::new(walked.node, key, walked.adopted))
+    }
+
+    /// Both ends met
+    #[inline]
+    fn finish(&mut self) {
+        self.front.clear();
+        self.back.clear();
+    }
+}
+
+impl <'a, K: 'a + KeyPrefix + Clone, V: 'a + Clone> Iterator for Range<'a, K, V> {
+    type Item = (K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let level = self.front.last_mut()?;
+            if let Some(value) = level.value.take() {
+                let key = level.key.clone();
+                if self.back_key.as_ref().is_some_and(|back_key| cmp_keys(&key, back_key) != Ordering::Less) {
+                    self.finish();
+                    return None;
+                }
+                if self.contains(&key) {
+                    self.front_key = Some(key.clone());
+                    return Some((key, value));
+                }
+                continue;
+            }
+            match level.children.next() {
+                Some(walked) => {
+                    if let Some(child) = self.step_in(&self.front, walked) {
+                        self.front.push(child);
                     }
                 }
+                None => {
+                    self.front.pop();
+                }
             }
         }
-        return None;
     }
-}
\ No newline at end of file
+}
+
+impl <'a, K: 'a + KeyPrefix + Clone, V: 'a + Clone> DoubleEndedIterator for Range<'a, K, V> {
+
+    /// Nodes are walked backwards, their descendants before them
+    fn next_back(&mut self) -> Option<Self::Item> {
+        loop {
+            let level = self.back.last_mut()?;
+            if let Some(walked) = level.children.next_back() {
+                if let Some(child) = self.step_in(&self.back, walked) {
+                    self.back.push(child);
+                }
+                continue;
+            }
+            let level = self.back.pop().unwrap();
+            if let Some(value) = level.value {
+                if self.front_key.as_ref().is_some_and(|front_key| cmp_keys(&level.key, front_key) != Ordering::Greater) {
+                    self.finish();
+                    return None;
+                }
+                if self.contains(&level.key) {
+                    self.back_key = Some(level.key.clone());
+                    return Some((level.key, value));
+                }
+            }
+        }
+    }
+}
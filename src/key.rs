@@ -1,28 +1,66 @@
 //! The Trie Key trait
 use std::sync::Arc;
+use std::borrow::Cow;
+use std::fmt::Debug;
 use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
+use crate::fold::Folding;
 use crate::matcher::{MatchType, StateSequence};
 
-/// The Trie Key prefix trait
+/// The Trie Key prefix trait.
+///
+/// Keys are sequences of symbols (`char`s for text keys, bytes for binary keys): every length
+/// and index below counts symbols, consistently with [TrieKey::lcp] and the events fed to the matchers
 pub trait KeyPrefix {
 
-    fn key_chars(&self) -> Vec<char>;
+    /// The key alphabet
+    type Symbol: Clone + Ord + Debug + Serialize + DeserializeOwned;
 
+    /// Borrowing iterator over the key symbols. Keys get compared on every insertion and lookup,
+    /// so the iterator itself must not allocate. Symbols are yielded by value: keys whose symbols
+    /// own heap data should share it (e.g. `Arc<str>`) for cloning them to stay allocation free
+    fn symbols(&self) -> impl Iterator<Item = Self::Symbol> + Clone + '_;
+
+    /// Number of symbols of the key
     fn key_len(&self) -> usize;
 
     fn empty() -> Self;
 
+    /// The first `index` symbols of the key
     fn new_from_key_prefix(&self, index: usize) -> Self;
 
+    /// The key without its first `index` symbols
     fn new_from_postfix(&self, index: usize) -> Self;
 
+    /// The key followed by the `postfix` symbols. Required since nodes get merged back on removal:
+    /// keys cannot be built out of symbols, so there is no default
+    fn new_from_concat(&self, postfix: &Self) -> Self;
+
+    /// Largest split position not beyond `index` which does not break a key token apart.
+    /// Nodes are only split at such positions, so each node key compiles on its own
     #[inline]
-    fn compiled(&self) -> Vec<Arc<StateSequence>> {
+    fn prefix_boundary(&self, index: usize) -> usize {
+        index
+    }
+
+    /// The key folded as told, for tries configured with a [Folding]. Left as it is by default
+    #[inline]
+    fn folded(&self, _folding: &Folding) -> Self where Self: Sized {
+        self.new_from_postfix(0)
+    }
+
+    /// The key folded as told when it is a match key rather than a stored one. Unlike pattern keys,
+    /// whose syntax never gets folded, match keys fold all their symbols alike. Same as
+    /// [KeyPrefix::folded] by default
+    #[inline]
+    fn folded_query(&self, folding: &Folding) -> Self where Self: Sized {
+        self.folded(folding)
+    }
+
+    #[inline]
+    fn compiled(&self) -> Vec<Arc<StateSequence<Self::Symbol>>> {
         let mut state_seq = Vec::new();
-        let mut buff: Vec<char> = Vec::new();
-        for ch in self.key_chars() {
-            buff.push(ch);
-        }
+        let buff: Vec<Self::Symbol> = self.symbols().collect();
         if !buff.is_empty() {
             state_seq.push(Arc::new(StateSequence {
                 match_type: MatchType::Literal,
@@ -33,10 +71,53 @@ pub trait KeyPrefix {
     }
 }
 
+/// A borrowed form of the `K` keys lookups accept (e.g. `str` for text keys), so queries need
+/// not be copied into keys. A query presents the very symbols of the key it stands for
+pub trait KeyQuery<K: KeyPrefix> {
+
+    fn query_symbols(&self) -> impl Iterator<Item = K::Symbol> + Clone + '_;
+
+    /// The key the query stands for, only built for tries folding their keys
+    fn to_key(&self) -> K;
+}
+
+impl<K: KeyPrefix + Clone> KeyQuery<K> for K {
+
+    #[inline]
+    fn query_symbols(&self) -> impl Iterator<Item = K::Symbol> + Clone + '_ {
+        self.symbols()
+    }
+
+    #[inline]
+    fn to_key(&self) -> K {
+        self.clone()
+    }
+}
+
+/// The match key symbols, either streamed from the query or from its folded key
+#[derive(Clone)]
+pub(crate) enum QuerySymbols<I: Iterator> {
+    Borrowed(I),
+    Folded(std::vec::IntoIter<I::Item>),
+}
+
+impl<I: Iterator> Iterator for QuerySymbols<I> {
+    type Item = I::Item;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            QuerySymbols::Borrowed(symbols) => symbols.next(),
+            QuerySymbols::Folded(symbols) => symbols.next(),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct TrieKey<K> {
+#[serde(bound(serialize = "K: Serialize", deserialize = "K: Deserialize<'de>"))]
+pub struct TrieKey<K: KeyPrefix> {
     pub(crate) key: K,
-    pub(crate) seq: Vec<Arc<StateSequence>>,
+    pub(crate) seq: Vec<Arc<StateSequence<K::Symbol>>>,
 }
 
 impl <K: KeyPrefix> TrieKey<K> {
@@ -54,43 +135,66 @@ impl <K: KeyPrefix> TrieKey<K> {
         self.seq.is_empty()
     }
 
+    /// Longest common prefix of both keys (down to a prefix boundary of both), whether `self`
+    /// preceeds `other`, and whether both keys are equal. No allocation is involved
     #[inline]
     pub fn lcp(&self, other: &Self) -> (usize, bool, bool) {
+        let (lcp, _, preceeding, full_match) = self.lcp_from(0, self.key.symbols(), other);
+        (lcp, preceeding, full_match)
+    }
+
+    /// Like [TrieKey::lcp], for the key postfix starting at the `offset` boundary (`rest` being its
+    /// symbols), so descending the trie does not build postfix keys. Also tells whether both share
+    /// their leading symbol, which they may do without sharing a boundary
+    #[inline]
+    pub(crate) fn lcp_from<I>(&self, offset: usize, mut rest: I, other: &Self) -> (usize, bool, bool, bool)
+        where I: Iterator<Item = K::Symbol>
+    {
         let mut lcp = 0;
-        let w0l = self.key.key_len();
-        let w1l = other.key.key_len();
-        let mlen = std::cmp::min(w0l, w1l);
-        let mut w0i = self.key.key_chars().into_iter();
-        let mut w1i = other.key.key_chars().into_iter();
-        let mut preceeding = true;
-        for _ in 0 .. mlen {
-            let char0 = w0i.next().unwrap();
-            let char1 = w1i.next().unwrap();
-            if char0.eq(&char1) {
-                lcp += 1;
+        let mut w1i = other.key.symbols();
+        let (preceeding, full_match) = loop {
+            match (rest.next(), w1i.next()) {
+                (Some(char0), Some(char1)) if char0 == char1 => lcp += 1,
+                (Some(char0), Some(char1)) => break (char0 < char1, false),
+                // A key preceeds the keys it is a prefix of
+                (None, next1) => break (true, next1.is_none()),
+                (Some(_), None) => break (false, false),
             }
-            else {
-                if char1.lt(&char0) {
-                    preceeding = false;
-                }
-                break;
+        };
+        let shared = lcp > 0;
+        if !full_match {
+            lcp = self.common_boundary(offset, other, lcp);
+        }
+        (lcp, shared, preceeding, full_match)
+    }
+
+    /// Largest prefix boundary of both keys not beyond `index`, `self` being taken from `offset` on
+    fn common_boundary(&self, offset: usize, other: &Self, mut index: usize) -> usize {
+        // Either key may hold a token straddling the boundaries of the other one: round both down in turns
+        while index > 0 {
+            let boundary = self.key.prefix_boundary(offset + index) - offset;
+            let other_boundary = other.key.prefix_boundary(boundary);
+            if other_boundary == boundary {
+                return boundary;
             }
+            index = other_boundary;
         }
-        let full_match = w0l == w1l && lcp == w0l;
-        (lcp, preceeding, full_match)
+        0
     }
 }
 
 impl KeyPrefix for String {
 
+    type Symbol = char;
+
     #[inline]
-    fn key_chars(&self) -> Vec<char> {
-        self.chars().into_iter().collect()
+    fn symbols(&self) -> impl Iterator<Item = char> + Clone + '_ {
+        self.chars()
     }
 
     #[inline]
     fn key_len(&self) -> usize {
-        self.len()
+        self.chars().count()
     }
 
     #[inline]
@@ -100,12 +204,261 @@ impl KeyPrefix for String {
 
     #[inline]
     fn new_from_key_prefix(&self, index: usize) -> Self {
-        self[..index].to_string()
+        self[..byte_offset(self, index)].to_string()
     }
 
     #[inline]
     fn new_from_postfix(&self, index: usize) -> Self {
-        self[index..].to_string()
+        self[byte_offset(self, index)..].to_string()
+    }
+
+    #[inline]
+    fn new_from_concat(&self, postfix: &Self) -> Self {
+        let mut key = self.clone();
+        key.push_str(postfix);
+        key
+    }
+
+    #[inline]
+    fn folded(&self, folding: &Folding) -> Self {
+        folding.fold(self)
+    }
+}
+
+impl KeyQuery<String> for str {
+
+    #[inline]
+    fn query_symbols(&self) -> impl Iterator<Item = char> + Clone + '_ {
+        self.chars()
+    }
+
+    #[inline]
+    fn to_key(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl KeyPrefix for Vec<u8> {
+
+    type Symbol = u8;
+
+    #[inline]
+    fn symbols(&self) -> impl Iterator<Item = u8> + Clone + '_ {
+        self.iter().copied()
+    }
+
+    #[inline]
+    fn key_len(&self) -> usize {
+        self.len()
+    }
+
+    #[inline]
+    fn empty() -> Self {
+        Vec::new()
+    }
+
+    #[inline]
+    fn new_from_key_prefix(&self, index: usize) -> Self {
+        self[..index].to_vec()
+    }
+
+    #[inline]
+    fn new_from_postfix(&self, index: usize) -> Self {
+        self[index..].to_vec()
+    }
+
+    #[inline]
+    fn new_from_concat(&self, postfix: &Self) -> Self {
+        [self.as_slice(), postfix.as_slice()].concat()
+    }
+}
+
+impl KeyQuery<Vec<u8>> for [u8] {
+
+    #[inline]
+    fn query_symbols(&self) -> impl Iterator<Item = u8> + Clone + '_ {
+        self.iter().copied()
+    }
+
+    #[inline]
+    fn to_key(&self) -> Vec<u8> {
+        self.to_vec()
+    }
+}
+
+impl KeyPrefix for Box<[u8]> {
+
+    type Symbol = u8;
+
+    #[inline]
+    fn symbols(&self) -> impl Iterator<Item = u8> + Clone + '_ {
+        self.iter().copied()
+    }
+
+    #[inline]
+    fn key_len(&self) -> usize {
+        self.len()
+    }
+
+    #[inline]
+    fn empty() -> Self {
+        Box::default()
+    }
+
+    #[inline]
+    fn new_from_key_prefix(&self, index: usize) -> Self {
+        self[..index].into()
+    }
+
+    #[inline]
+    fn new_from_postfix(&self, index: usize) -> Self {
+        self[index..].into()
+    }
+
+    #[inline]
+    fn new_from_concat(&self, postfix: &Self) -> Self {
+        [&self[..], &postfix[..]].concat().into_boxed_slice()
+    }
+}
+
+impl KeyQuery<Box<[u8]>> for [u8] {
+
+    #[inline]
+    fn query_symbols(&self) -> impl Iterator<Item = u8> + Clone + '_ {
+        self.iter().copied()
+    }
+
+    #[inline]
+    fn to_key(&self) -> Box<[u8]> {
+        self.into()
+    }
+}
+
+/// Borrowed byte keys. Plain `&[u8]` keys cannot be supported, as node keys get concatenated
+/// (on removals and iteration), which needs to allocate
+impl<'a> KeyPrefix for Cow<'a, [u8]> {
+
+    type Symbol = u8;
+
+    #[inline]
+    fn symbols(&self) -> impl Iterator<Item = u8> + Clone + '_ {
+        self.iter().copied()
+    }
+
+    #[inline]
+    fn key_len(&self) -> usize {
+        self.len()
+    }
+
+    #[inline]
+    fn empty() -> Self {
+        Cow::Borrowed(&[])
+    }
+
+    #[inline]
+    fn new_from_key_prefix(&self, index: usize) -> Self {
+        match self {
+            Cow::Borrowed(bytes) => Cow::Borrowed(&bytes[..index]),
+            Cow::Owned(bytes) => Cow::Owned(bytes[..index].to_vec()),
+        }
+    }
+
+    #[inline]
+    fn new_from_postfix(&self, index: usize) -> Self {
+        match self {
+            Cow::Borrowed(bytes) => Cow::Borrowed(&bytes[index..]),
+            Cow::Owned(bytes) => Cow::Owned(bytes[index..].to_vec()),
+        }
+    }
+
+    #[inline]
+    fn new_from_concat(&self, postfix: &Self) -> Self {
+        if postfix.is_empty() {
+            self.clone()
+        }
+        else if self.is_empty() {
+            postfix.clone()
+        }
+        else {
+            Cow::Owned([&self[..], &postfix[..]].concat())
+        }
+    }
+}
+
+impl<'a> KeyQuery<Cow<'a, [u8]>> for [u8] {
+
+    #[inline]
+    fn query_symbols(&self) -> impl Iterator<Item = u8> + Clone + '_ {
+        self.iter().copied()
+    }
+
+    #[inline]
+    fn to_key(&self) -> Cow<'a, [u8]> {
+        Cow::Owned(self.to_vec())
+    }
+}
+
+/// The [KeyPrefix] items shared by the key types wrapping their text in a single `String` field,
+/// which is sliced and concatenated as `String` keys are. Key syntaxes add their own folding,
+/// prefix boundaries and compilation
+macro_rules! text_key_prefix {
+    ($field:ident) => {
+        type Symbol = char;
+
+        #[inline]
+        fn symbols(&self) -> impl Iterator<Item = char> + Clone + '_ {
+            self.$field.symbols()
+        }
+
+        #[inline]
+        fn key_len(&self) -> usize {
+            self.$field.key_len()
+        }
+
+        #[inline]
+        fn empty() -> Self {
+            Self {
+                $field: String::new(),
+            }
+        }
+
+        #[inline]
+        fn new_from_key_prefix(&self, index: usize) -> Self {
+            Self {
+                $field: self.$field.new_from_key_prefix(index)
+            }
+        }
+
+        #[inline]
+        fn new_from_postfix(&self, index: usize) -> Self {
+            Self {
+                $field: self.$field.new_from_postfix(index)
+            }
+        }
+
+        #[inline]
+        fn new_from_concat(&self, postfix: &Self) -> Self {
+            Self {
+                $field: self.$field.new_from_concat(&postfix.$field)
+            }
+        }
+
+    };
+}
+
+pub(crate) use text_key_prefix;
+
+/// Byte offset of the char at `index` in `text`, or its length past the last char
+#[inline]
+pub(crate) fn byte_offset(text: &str, index: usize) -> usize {
+    text.char_indices().nth(index).map_or(text.len(), |(offset, _)| offset)
+}
+
+/// Skips the next `count` items of `iter`
+#[inline]
+pub(crate) fn advance<I: Iterator>(iter: &mut I, count: usize) {
+    if count > 0 {
+        iter.nth(count - 1);
     }
 }
 
@@ -114,3 +467,16 @@ pub trait ValueMerge {
 
     fn merge_mut(&mut self, other: &Self);
 }
+
+/// Merged lists hold the items of both, as subscriber lists do
+impl<T: Clone> ValueMerge for Vec<T> {
+    fn merge(&self, other: &Self) -> Self {
+        let mut merged = self.clone();
+        merged.extend_from_slice(other);
+        merged
+    }
+
+    fn merge_mut(&mut self, other: &Self) {
+        self.extend_from_slice(other)
+    }
+}
@@ -0,0 +1,187 @@
+//! Fuzzy matching of the stored keys within an edit distance of the match key
+//!
+//! The distances are computed a trie node at a time, so whole subtrees get pruned as soon as
+//! no stored key below them can get close enough. Key tokens are taken as literals.
+use std::sync::Arc;
+use crate::matcher::{Event, PushdownStateMachine, State, StateSequence};
+
+#[derive(Debug, Clone)]
+struct Distances<S> {
+    /// Match key symbols fed so far
+    query: Vec<S>,
+    end_of_stream: bool,
+    /// Stored key symbols stepped in so far
+    key: Vec<S>,
+    /// Last two rows of the distance matrix, once the whole match key is known
+    previous: Vec<usize>,
+    current: Vec<usize>,
+}
+
+impl<S: Clone + Eq> Distances<S> {
+
+    #[inline]
+    fn new() -> Self {
+        Self {
+            query: Vec::new(),
+            end_of_stream: false,
+            key: Vec::new(),
+            previous: Vec::new(),
+            current: Vec::new(),
+        }
+    }
+
+    /// Computes the distances of the stored key extended with `ch`
+    fn push_row(&mut self, ch: S, transpositions: bool) {
+        let row_index = self.key.len() + 1;
+        let mut row = vec![row_index; self.query.len() + 1];
+        for column in 1..=self.query.len() {
+            let substitution = self.current[column - 1] + usize::from(self.query[column - 1] != ch);
+            row[column] = substitution
+                .min(self.current[column] + 1)
+                .min(row[column - 1] + 1);
+            if transpositions && column > 1 && row_index > 1
+                && self.query[column - 1] == self.key[row_index - 2] && self.query[column - 2] == ch {
+                row[column] = row[column].min(self.previous[column - 2] + 1);
+            }
+        }
+        self.previous = std::mem::replace(&mut self.current, row);
+        self.key.push(ch);
+    }
+
+    #[inline]
+    fn distance(&self) -> Option<usize> {
+        self.current.last().copied()
+    }
+}
+
+/// Accepts the stored keys within `MAX_DISTANCE` edits (insertions, deletions and substitutions,
+/// plus adjacent transpositions with `TRANSPOSITIONS`) of the match key. The distance of each
+/// accepted key is reported as its cost. Keys of any alphabet `S` are supported
+#[derive(Debug, Clone)]
+pub struct LevenshteinMatcher<const MAX_DISTANCE: usize = 1, const TRANSPOSITIONS: bool = false, S = char> {
+    stack: Vec<Distances<S>>,
+}
+
+impl<const MAX_DISTANCE: usize, const TRANSPOSITIONS: bool, S: Clone + Eq> PushdownStateMachine<S> for LevenshteinMatcher<MAX_DISTANCE, TRANSPOSITIONS, S> {
+    fn new() -> Self {
+        Self {
+            stack: Vec::new(),
+        }
+    }
+
+    #[inline]
+    fn step_in(&mut self, sequence: &Vec<Arc<StateSequence<S>>>) {
+        let mut distances = self.stack.last().cloned().unwrap_or_else(Distances::new);
+        for ch in sequence.iter().flat_map(|token| token.sequence.iter()) {
+            if distances.end_of_stream {
+                distances.push_row(ch.clone(), TRANSPOSITIONS);
+            }
+            else {
+                distances.key.push(ch.clone());
+            }
+        }
+        self.stack.push(distances);
+    }
+
+    #[inline]
+    fn step_out(&mut self) {
+        let _k = self.stack.pop();
+    }
+
+    /// The whole match key is needed before computing any distance
+    #[inline]
+    fn accepts_more(&self) -> bool {
+        match self.stack.last() {
+            Some(distances) => !distances.end_of_stream,
+            None => false
+        }
+    }
+
+    #[inline]
+    fn feed(&mut self, ev: Event<S>) {
+        if let Some(distances) = self.stack.last_mut() {
+            match ev {
+                Event::CharIn(ch) => {
+                    distances.query.push(ch);
+                }
+                Event::EndOfStream if !distances.end_of_stream => {
+                    distances.end_of_stream = true;
+                    distances.current = (0..=distances.query.len()).collect();
+                    let key = std::mem::take(&mut distances.key);
+                    for ch in key {
+                        distances.push_row(ch, TRANSPOSITIONS);
+                    }
+                }
+                Event::EndOfStream => {}
+            }
+        }
+    }
+
+    #[inline]
+    fn state(&self) -> State {
+        match self.stack.last() {
+            Some(distances) if !distances.end_of_stream => State::Expecting,
+            Some(distances) => {
+                if distances.distance().is_some_and(|distance| distance <= MAX_DISTANCE) {
+                    State::Accepted
+                }
+                else if distances.current.iter().any(|distance| *distance <= MAX_DISTANCE) {
+                    // Longer stored keys may get closer
+                    State::Expecting
+                }
+                else {
+                    State::Rejected
+                }
+            }
+            None => State::Failure(String::from("Machine not initiallized"))
+        }
+    }
+
+    #[inline]
+    fn is_sink(&self) -> bool {
+        !matches!(self.state(), State::Accepting | State::Expecting)
+    }
+
+    #[inline]
+    fn cost(&self) -> usize {
+        self.stack.last().and_then(Distances::distance).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::trie::Trie;
+    use crate::levenshtein::*;
+
+    #[test]
+    fn levenshtein_test() {
+        let trie: Trie<String, i32> = ["apple", "apply", "ape", "maple", "applesauce", "banana", "pale", "aplpe"]
+            .iter()
+            .enumerate()
+            .map(|(index, key)| (key.to_string(), index as i32))
+            .collect();
+
+        let ranked = |trie: &Trie<String, i32>, query: &str| {
+            trie.matches_ranked::<LevenshteinMatcher<2>>(&query.to_string())
+                .into_iter()
+                .map(|(key, _, distance)| (key, distance))
+                .collect::<Vec<_>>()
+        };
+        let owned = |expected: &[(&str, usize)]| expected.iter()
+            .map(|(key, distance)| (key.to_string(), *distance))
+            .collect::<Vec<_>>();
+        assert_eq!(owned(&[("apple", 0), ("apply", 1), ("ape", 2), ("aplpe", 2), ("maple", 2), ("pale", 2)]), ranked(&trie, "apple"));
+        assert_eq!(owned(&[("ape", 1), ("pale", 2)]), ranked(&trie, "pe"));
+        assert_eq!(Vec::<(String, usize)>::new(), ranked(&trie, "xyz"));
+        assert_eq!(owned(&[("ape", 3)]), trie.matches_ranked::<LevenshteinMatcher<3>>(&String::new())
+            .into_iter().map(|(key, _, distance)| (key, distance)).collect::<Vec<_>>());
+
+        // Transpositions count as a single edit
+        assert_eq!(None, trie.get::<LevenshteinMatcher<1>>(&String::from("bnaana")));
+        assert_eq!(Some(5), trie.get::<LevenshteinMatcher<1, true>>(&String::from("bnaana")));
+        let matches: Vec<String> = trie.matches::<LevenshteinMatcher<1, true>>(&String::from("apple"))
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(vec!["aplpe", "apple", "apply"], matches);
+    }
+}
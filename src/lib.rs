@@ -2,16 +2,28 @@
 
 pub mod trie;
 pub mod key;
-pub mod node;
+mod node;
 pub mod matcher;
 pub mod iterator;
+pub mod entry;
 pub mod glob;
+pub mod topic;
+pub mod regex;
+pub mod levenshtein;
+pub mod fold;
+pub mod literal;
+pub mod segment;
+mod nfa;
 
 #[cfg(test)]
 mod tests {
     use crate::trie::Trie;
-    use crate::glob::GlobMatcher;
+    use crate::glob::{GlobMatcher, PathGlobMatcher};
     use crate::glob::acl::{AclTrie, Acl, Permissions};
+    use crate::matcher::Capture;
+    use crate::key::KeyPrefix;
+    use std::collections::BTreeMap;
+    use proptest::prelude::*;
 
     #[test]
     fn functional_test() {
@@ -166,6 +178,473 @@ mod tests {
     }
 
     #[test]
+    fn remove_test() {
+        let mut trie = AclTrie::new();
+        trie.insert(Acl::new("/path/*"), Permissions::READ);
+        trie.insert(Acl::new("/path/to/resource"), Permissions::WRITE);
+        trie.insert(Acl::new("/path/to/other"), Permissions::CREATE);
+
+        assert!(trie.remove(&Acl::new("/path/to")).is_none());
+        assert!(trie.remove(&Acl::new("/path/to/resourc")).is_none());
+
+        // Pattern keys are removed literally
+        assert_eq!(Some(Permissions::READ), trie.remove(&Acl::new("/path/*")));
+        assert!(trie.remove(&Acl::new("/path/*")).is_none());
+        assert!(trie.get::<GlobMatcher>(&Acl::new("/path/other")).is_none());
+        assert_eq!(Permissions::WRITE, trie.get::<GlobMatcher>(&Acl::new("/path/to/resource")).unwrap());
+
+        // Remaining aux nodes are merged back
+        assert_eq!(Some(Permissions::WRITE), trie.remove(&Acl::new("/path/to/resource")));
+        let nodes = std::cell::RefCell::new(Vec::new());
+        trie.foreach(|tup| nodes.borrow_mut().push((tup.0, tup.1.to_string())));
+        assert_eq!(vec![(1, String::from("/path/to/other"))], nodes.into_inner());
+        assert_eq!(Permissions::CREATE, trie.get::<GlobMatcher>(&Acl::new("/path/to/other")).unwrap());
+
+        assert_eq!(Some(Permissions::CREATE), trie.remove(&Acl::new("/path/to/other")));
+        assert!(trie.iter().next().is_none());
+
+        let mut trie = Trie::new();
+        trie.insert(String::from("aaaa"), 1);
+        trie.insert(String::from("aa"), 2);
+        trie.insert(String::from("aabb"), 3);
+        assert!(trie.insert(String::from("aabb"), 4).is_some());
+        assert_eq!(Some(2), trie.remove(&String::from("aa")));
+        assert_eq!(Some(1), trie.get::<GlobMatcher>(&String::from("aaaa")));
+        assert_eq!(Some(4), trie.get::<GlobMatcher>(&String::from("aabb")));
+        assert!(trie.get::<GlobMatcher>(&String::from("aa")).is_none());
+        assert_eq!(Some(1), trie.remove(&String::from("aaaa")));
+        let nodes = std::cell::RefCell::new(Vec::new());
+        trie.foreach(|tup| nodes.borrow_mut().push((tup.0, tup.1.clone())));
+        assert_eq!(vec![(1, String::from("aabb"))], nodes.into_inner());
+    }
+
+    #[test]
+    fn matched_key_test() {
+        let mut trie = AclTrie::new();
+        trie.insert(Acl::new("/path/*"), Permissions::READ);
+        trie.insert(Acl::new("/path/to/resource"), Permissions::WRITE);
+
+        let (key, value) = trie.get_with_key::<GlobMatcher>(&Acl::new("/path/other")).unwrap();
+        assert_eq!("/path/*", key.path);
+        assert_eq!(&Permissions::READ, value);
+        assert!(trie.get_with_key::<GlobMatcher>(&Acl::new("/other")).is_none());
+
+        let (value, keys) = trie.get_merge_with_keys::<GlobMatcher>(&Acl::new("/path/to/resource")).unwrap();
+        assert_eq!(Permissions::READ | Permissions::WRITE, value);
+        assert_eq!(vec![Acl::new("/path/*"), Acl::new("/path/to/resource")], keys);
+        assert!(trie.get_merge_with_keys::<GlobMatcher>(&Acl::new("/other")).is_none());
+    }
+
+    #[test]
+    fn matches_test() {
+        let mut trie = AclTrie::new();
+        trie.insert(Acl::new("/path/*"), Permissions::READ);
+        trie.insert(Acl::new("/path/to/*"), Permissions::empty());
+        trie.insert(Acl::new("/path/to/resource"), Permissions::WRITE);
+
+        let query = Acl::new("/path/to/resource");
+        let keys: Vec<String> = trie.matches::<GlobMatcher>(&query).map(|(key, _)| key.path).collect();
+        assert_eq!(vec!["/path/*", "/path/to/*", "/path/to/resource"], keys);
+        assert_eq!(2, trie.matches::<GlobMatcher>(&query).take(2).count());
+
+        // Short-circuit on a deny rule
+        let denied = trie.matches::<GlobMatcher>(&query)
+            .any(|(_, permissions)| permissions.is_empty());
+        assert!(denied);
+        let denied = trie.matches::<GlobMatcher>(&Acl::new("/path/other"))
+            .any(|(_, permissions)| permissions.is_empty());
+        assert!(!denied);
+        assert!(trie.matches::<GlobMatcher>(&Acl::new("/other")).next().is_none());
+
+        // Siblings are still visited after a subtree fails to match
+        let mut trie = AclTrie::new();
+        trie.insert(Acl::new("x(1"), Permissions::WRITE);
+        trie.insert(Acl::new("x(2"), Permissions::WRITE);
+        trie.insert(Acl::new("x*"), Permissions::READ);
+        assert_eq!(Permissions::READ, trie.get::<GlobMatcher>(&Acl::new("x(0")).unwrap());
+        assert_eq!(Permissions::READ, trie.get::<GlobMatcher>(&Acl::new("x(3")).unwrap());
+        assert_eq!(Permissions::READ, trie.get::<GlobMatcher>(&Acl::new("x(")).unwrap());
+        assert_eq!(Permissions::READ | Permissions::WRITE, trie.get_merge::<GlobMatcher>(&Acl::new("x(2")).unwrap());
+    }
+
+    #[test]
+    fn captures_test() {
+        let mut trie = AclTrie::new();
+        trie.insert(Acl::new("/users/*/profile"), Permissions::READ);
+        trie.insert(Acl::new("/users/*/posts/*"), Permissions::WRITE);
+        trie.insert(Acl::new("/users/admin"), Permissions::OWNER);
+
+        let query = "/users/42/profile";
+        let (key, value, captures) = trie.get_with_captures::<GlobMatcher>(&Acl::new(query)).unwrap();
+        assert_eq!("/users/*/profile", key.path);
+        assert_eq!(&Permissions::READ, value);
+        let captured: Vec<&str> = captures.iter().map(|capture| capture.slice(query)).collect();
+        assert_eq!(vec!["42"], captured);
+
+        let query = "/users/42/posts/7";
+        let (key, _, captures) = trie.get_with_captures::<GlobMatcher>(&Acl::new(query)).unwrap();
+        assert_eq!("/users/*/posts/*", key.path);
+        let captured: Vec<&str> = captures.iter().map(|capture| capture.slice(query)).collect();
+        assert_eq!(vec!["42", "7"], captured);
+
+        let (key, _, captures) = trie.get_with_captures::<GlobMatcher>(&Acl::new("/users/admin")).unwrap();
+        assert_eq!("/users/admin", key.path);
+        assert!(captures.is_empty());
+        assert!(trie.get_with_captures::<GlobMatcher>(&Acl::new("/users/42")).is_none());
+
+        // Keys below an accepted one are still reached
+        let mut trie = AclTrie::new();
+        trie.insert(Acl::new("a*"), Permissions::READ);
+        trie.insert(Acl::new("a*b"), Permissions::WRITE);
+        trie.insert(Acl::new("a*c"), Permissions::CREATE);
+        assert_eq!(Permissions::READ | Permissions::WRITE, trie.get_merge::<GlobMatcher>(&Acl::new("axxb")).unwrap());
+        assert_eq!(Permissions::READ | Permissions::WRITE, trie.get_merge::<GlobMatcher>(&Acl::new("ab")).unwrap());
+        let mut matches = trie.matches::<GlobMatcher>("abc");
+        assert_eq!("a*", matches.next().unwrap().0.path);
+        assert_eq!(vec![Capture { start: 1, end: 3 }], matches.captures());
+        assert_eq!("a*c", matches.next().unwrap().0.path);
+        assert_eq!(vec![Capture { start: 1, end: 2 }], matches.captures());
+        assert!(matches.next().is_none());
+    }
+
+    #[test]
+    fn exact_test() {
+        let mut trie = AclTrie::new();
+        trie.insert(Acl::new("/path/*"), Permissions::READ);
+        trie.insert(Acl::new("/path/to/resource"), Permissions::WRITE);
+        trie.insert(Acl::new("/path/to"), Permissions::CREATE);
+
+        assert_eq!(Some(&Permissions::READ), trie.get_exact(&Acl::new("/path/*")));
+        assert_eq!(Some(&Permissions::WRITE), trie.get_exact(&Acl::new("/path/to/resource")));
+        assert_eq!(Some(&Permissions::CREATE), trie.get_exact(&Acl::new("/path/to")));
+        assert!(trie.contains_key(&Acl::new("/path/*")));
+
+        // Patterns are not interpreted
+        assert!(trie.get_exact(&Acl::new("/path/other")).is_none());
+        assert!(!trie.contains_key(&Acl::new("/path/other")));
+        assert!(!trie.contains_key(&Acl::new("/path/")));
+        assert!(!trie.contains_key(&Acl::new("/path/to/resource/")));
+        assert!(!trie.contains_key(&Acl::new("/pa")));
+        assert!(trie.get::<GlobMatcher>(&Acl::new("/path/other")).is_some());
+
+        // Siblings sharing leading symbols but no token boundary
+        let mut trie = AclTrie::new();
+        trie.insert(Acl::new("\\"), Permissions::READ);
+        trie.insert(Acl::new("\\*"), Permissions::WRITE);
+        assert_eq!(Some(&Permissions::READ), trie.get_exact(&Acl::new("\\")));
+        assert_eq!(Some(&Permissions::WRITE), trie.get_exact(&Acl::new("\\*")));
+
+        let mut trie = AclTrie::new();
+        trie.insert(Acl::new("[!a]"), Permissions::READ);
+        trie.insert(Acl::new("[a"), Permissions::WRITE);
+        trie.insert(Acl::new("["), Permissions::CREATE);
+        assert!(trie.contains_key(&Acl::new("[a")));
+        assert_eq!(Some(Permissions::WRITE), trie.insert(Acl::new("[a"), Permissions::WRITE));
+        assert_eq!(3, trie.len());
+        assert_eq!(3, trie.keys().count());
+
+        let mut trie = AclTrie::new();
+        trie.insert(Acl::new("[\\"), Permissions::READ);
+        trie.insert(Acl::new("[!a]"), Permissions::WRITE);
+        trie.insert(Acl::new("["), Permissions::CREATE);
+        for key in ["[\\", "[!a]", "["] {
+            assert!(trie.contains_key(&Acl::new(key)), "{}", key);
+        }
+    }
+
+    #[test]
+    fn longest_prefix_test() {
+        let mut routes = Trie::new();
+        routes.insert(String::new(), "default");
+        routes.insert(String::from("10."), "private");
+        routes.insert(String::from("10.1."), "site");
+        routes.insert(String::from("10.1.2."), "rack");
+        routes.insert(String::from("10.12."), "other site");
+
+        assert_eq!(Some((String::from("10.1.2."), &"rack")), routes.longest_prefix("10.1.2.3"));
+        assert_eq!(Some((String::from("10.1."), &"site")), routes.longest_prefix("10.1.3.1"));
+        assert_eq!(Some((String::new(), &"default")), routes.longest_prefix("192.168.0.1"));
+        assert_eq!(vec!["default", "private", "site", "rack"], routes.prefixes_of("10.1.2.3").into_iter().map(|(_, value)| *value).collect::<Vec<_>>());
+        assert_eq!(vec!["default", "private", "other site"], routes.prefixes_of(&String::from("10.12.0.1")).into_iter().map(|(_, value)| *value).collect::<Vec<_>>());
+        routes.remove(&String::new());
+        assert_eq!(None, routes.longest_prefix("192.168.0.1"));
+
+        // Patterns are compared literally
+        let mut trie = AclTrie::new();
+        trie.insert(Acl::new("/path/*"), Permissions::READ);
+        trie.insert(Acl::new("/path/**"), Permissions::WRITE);
+        trie.insert(Acl::new("/path/to"), Permissions::CREATE);
+        assert_eq!(None, trie.longest_prefix("/path/x"));
+        assert_eq!(vec![Acl::new("/path/*"), Acl::new("/path/**")], trie.prefixes_of("/path/**/x").into_iter().map(|(key, _)| key).collect::<Vec<_>>());
+        assert_eq!(Some((Acl::new("/path/to"), &Permissions::CREATE)), trie.longest_prefix("/path/together"));
+    }
+
+    #[test]
+    fn prefix_subtree_test() {
+        let mut trie = AclTrie::new();
+        for (path, permissions) in [
+            ("/tenant/42/a", Permissions::READ),
+            ("/tenant/42/b/*", Permissions::WRITE),
+            ("/tenant/42", Permissions::OWNER),
+            ("/tenant/420/a", Permissions::READ),
+            ("/tenant/43/a", Permissions::READ),
+            ("/tenant/*", Permissions::WATCH),
+            ("/tenant/**", Permissions::DELETE),
+        ] {
+            trie.insert(Acl::new(path), permissions);
+        }
+        let keys = |trie: &AclTrie, prefix: &str| trie.iter_prefix(prefix).map(|(key, _)| key.path).collect::<Vec<_>>();
+        assert_eq!(vec!["/tenant/42/a", "/tenant/42/b/*"], keys(&trie, "/tenant/42/"));
+        assert_eq!(vec!["/tenant/42", "/tenant/42/a", "/tenant/42/b/*", "/tenant/420/a"], keys(&trie, "/tenant/42"));
+        // Prefix ending in the middle of a node key
+        assert_eq!(vec!["/tenant/420/a"], keys(&trie, "/tenant/420/"));
+        assert_eq!(vec!["/tenant/*", "/tenant/**"], keys(&trie, "/tenant/*"));
+        trie.insert(Acl::new("/tenant/*b"), Permissions::READ);
+        assert_eq!(vec!["/tenant/*", "/tenant/**", "/tenant/*b"], keys(&trie, "/tenant/*"));
+        trie.remove(&Acl::new("/tenant/*b"));
+        assert_eq!(trie.len(), keys(&trie, "").len());
+        assert_eq!(Vec::<String>::new(), keys(&trie, "/tenant/5"));
+        assert_eq!(5, trie.iter_prefix("/tenant/4").count());
+        assert_eq!(4, trie.count_prefix("/tenant/42"));
+        assert_eq!(0, trie.count_prefix("/other"));
+
+        let subtrie = trie.subtrie("/tenant/42");
+        assert_eq!(4, subtrie.len());
+        assert_eq!(Some(&Permissions::OWNER), subtrie.get_exact(&Acl::new("/tenant/42")));
+        assert_eq!(Some(Permissions::WRITE), subtrie.get::<GlobMatcher>("/tenant/42/b/c"));
+        assert_eq!(7, trie.len());
+
+        let removed = trie.remove_prefix("/tenant/42/");
+        assert_eq!(vec!["/tenant/42/a", "/tenant/42/b/*"], removed.keys().map(|key| key.path).collect::<Vec<_>>());
+        assert_eq!(5, trie.len());
+        assert_eq!(None, trie.get_exact(&Acl::new("/tenant/42/a")));
+        assert_eq!(Some(&Permissions::OWNER), trie.get_exact(&Acl::new("/tenant/42")));
+        assert_eq!(Some(Permissions::WATCH | Permissions::DELETE), trie.get_merge::<GlobMatcher>("/tenant/42/a"));
+        for (key, value) in &trie {
+            assert_eq!(Some(value), trie.get_exact(&key));
+        }
+
+        let removed = trie.remove_prefix("/tenant/*");
+        assert_eq!(2, removed.len());
+        assert_eq!(Some(&Permissions::DELETE), removed.get_exact(&Acl::new("/tenant/**")));
+        assert_eq!(vec!["/tenant/42", "/tenant/420/a", "/tenant/43/a"], trie.keys().map(|key| key.path).collect::<Vec<_>>());
+        assert_eq!(3, trie.remove_prefix("").len());
+        assert!(trie.is_empty());
+        assert_eq!(0, trie.iter().count());
+    }
+
+    #[test]
+    fn range_test() {
+        let trie: Trie<String, usize> = ["b", "ba", "bab", "c", "ca", "d"].iter()
+            .enumerate()
+            .map(|(index, key)| (key.to_string(), index))
+            .collect();
+        let keys = |range: crate::iterator::Range<'_, String, usize>| range.map(|(key, _)| key).collect::<Vec<_>>();
+        assert_eq!(vec!["ba", "bab", "c"], keys(trie.range(String::from("ba")..String::from("ca"))));
+        assert_eq!(vec!["bab", "c", "ca"], keys(trie.range(String::from("baa")..=String::from("ca"))));
+        assert_eq!(vec!["b", "ba"], keys(trie.range(..String::from("bab"))));
+        assert_eq!(vec!["ca", "d"], keys(trie.range(String::from("c0")..)));
+        assert_eq!(Vec::<String>::new(), keys(trie.range(String::from("e")..)));
+        assert_eq!(trie.keys().collect::<Vec<_>>().into_iter().rev().collect::<Vec<_>>(), trie.range(..).rev().map(|(key, _)| key).collect::<Vec<_>>());
+
+        // Both ends meet without yielding a key twice
+        let mut range = trie.range(String::from("b")..String::from("d"));
+        assert_eq!(Some(String::from("b")), range.next().map(|(key, _)| key));
+        assert_eq!(Some(String::from("ca")), range.next_back().map(|(key, _)| key));
+        assert_eq!(Some(String::from("c")), range.next_back().map(|(key, _)| key));
+        assert_eq!(Some(String::from("ba")), range.next().map(|(key, _)| key));
+        assert_eq!(Some(String::from("bab")), range.next().map(|(key, _)| key));
+        assert_eq!(None, range.next());
+        assert_eq!(None, range.next_back());
+
+        assert_eq!(Some((String::from("b"), &0)), trie.first());
+        assert_eq!(Some((String::from("d"), &5)), trie.last());
+        assert_eq!(Some((String::from("ba"), &1)), trie.predecessor("bab"));
+        assert_eq!(Some((String::from("bab"), &2)), trie.predecessor("bb"));
+        assert_eq!(Some((String::from("c"), &3)), trie.successor("bab"));
+        assert_eq!(None, trie.predecessor("b"));
+        assert_eq!(None, trie.successor("d"));
+        assert_eq!(None, Trie::<String, usize>::new().last());
+
+        // Paginating ACL rules, pattern keys included, as the trie iterates them
+        let mut trie = AclTrie::new();
+        for path in ["/a/x*", "/a/x*a", "/a/x**", "/a/x*\\*", "/a/y", "/b"] {
+            trie.insert(Acl::new(path), Permissions::READ);
+        }
+        let all = trie.keys().collect::<Vec<_>>();
+        let mut pages = Vec::new();
+        let mut page = trie.range(..).take(2).map(|(key, _)| key).collect::<Vec<_>>();
+        while let Some(last) = page.last().cloned() {
+            pages.extend(page);
+            page = trie.range((std::ops::Bound::Excluded(last), std::ops::Bound::Unbounded)).take(2).map(|(key, _)| key).collect();
+        }
+        assert_eq!(all, pages);
+        for window in all.windows(2) {
+            assert_eq!(Some(window[0].clone()), trie.predecessor(&window[1]).map(|(key, _)| key));
+            assert_eq!(Some(window[1].clone()), trie.successor(&window[0]).map(|(key, _)| key));
+        }
+
+        // Pattern keys compare symbol by symbol, `**` sorting before `*b`
+        let mut trie = AclTrie::new();
+        for path in ["/a/*", "/a/*b", "/a/**"] {
+            trie.insert(Acl::new(path), Permissions::READ);
+        }
+        let paths = |keys: Vec<Acl>| keys.into_iter().map(|key| key.path).collect::<Vec<_>>();
+        assert_eq!(vec!["/a/*", "/a/**", "/a/*b"], paths(trie.keys().collect()));
+        assert_eq!(Some(String::from("/a/*b")), trie.last().map(|(key, _)| key.path));
+        assert_eq!(Some(String::from("/a/*b")), trie.successor("/a/**").map(|(key, _)| key.path));
+        assert_eq!(Some(String::from("/a/*")), trie.predecessor("/a/**").map(|(key, _)| key.path));
+        assert_eq!(vec!["/a/**", "/a/*b"], paths(trie.range(Acl::new("/a/**")..).map(|(key, _)| key).collect()));
+        assert_eq!(vec!["/a/*b", "/a/**", "/a/*"], paths(trie.range(..).rev().map(|(key, _)| key).collect()));
+        assert_eq!(vec!["/a/*", "/a/**"], paths(trie.range(..Acl::new("/a/*b")).map(|(key, _)| key).collect()));
+    }
+
+    #[test]
+    fn matched_by_test() {
+        let names = [
+            "/logs/2023-12/error.log", "/logs/2024-01/error.log", "/logs/2024-01/access.log",
+            "/logs/2024-02/error", "/logs/2024-02/old/error.log", "/logs/2024-", "/data/2024-01/error.log",
+        ];
+        let trie: Trie<String, usize> = names.iter().enumerate().map(|(index, name)| (name.to_string(), index)).collect();
+        let matched = |pattern: &str| trie.matched_by::<PathGlobMatcher>(&Acl::new(pattern).compiled())
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["/logs/2024-01/error.log", "/logs/2024-02/error"], matched("/logs/2024-*/error*"));
+        assert_eq!(vec!["/logs/2024-02/old/error.log"], matched("/logs/2024-*/*/error*"));
+        assert_eq!(vec!["/data/2024-01/error.log", "/logs/2024-01/error.log", "/logs/2024-02/error", "/logs/2024-02/old/error.log"], matched("/**/2024-*/**/error*"));
+        assert_eq!(vec!["/logs/2024-"], matched("/logs/2024-"));
+        assert_eq!(Vec::<String>::new(), matched("/tmp/*"));
+        assert_eq!(Some(&4), trie.matched_by::<PathGlobMatcher>(&Acl::new("/logs/*/old/*").compiled()).first().map(|(_, value)| *value));
+
+        // Same outcome as matching each stored key against a trie holding the pattern
+        for pattern in ["/logs/*", "/logs/**", "/**/error.log", "/logs/2024-0[12]/*.log", "/*/*/e*", "**"] {
+            let mut patterns = AclTrie::new();
+            patterns.insert(Acl::new(pattern), Permissions::READ);
+            let expected: Vec<String> = trie.keys()
+                .filter(|key| patterns.get::<PathGlobMatcher>(key.as_str()).is_some())
+                .collect();
+            assert_eq!(expected, matched(pattern), "{}", pattern);
+        }
+
+        // Stored keys extending their sibling's key, in key order
+        let mut trie = AclTrie::new();
+        for path in ["/a/*", "/a/*b", "/a/**"] {
+            trie.insert(Acl::new(path), Permissions::READ);
+        }
+        assert_eq!(vec!["/a/*", "/a/**", "/a/*b"], trie.matched_by::<PathGlobMatcher>(&Acl::new("/a/**").compiled())
+            .into_iter()
+            .map(|(key, _)| key.path)
+            .collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn entry_test() {
+        let mut trie = AclTrie::new();
+        trie.insert(Acl::new("/path/*"), Permissions::READ);
+        trie.insert(Acl::new("/path/to/resource"), Permissions::WRITE);
+
+        *trie.get_mut(&Acl::new("/path/*")).unwrap() |= Permissions::WATCH;
+        assert_eq!(Some(&(Permissions::READ | Permissions::WATCH)), trie.get_exact(&Acl::new("/path/*")));
+        assert!(trie.get_mut(&Acl::new("/path/")).is_none());
+
+        trie.entry(Acl::new("/path/to/resource"))
+            .and_modify(|permissions| *permissions |= Permissions::DELETE)
+            .or_insert(Permissions::empty());
+        assert_eq!(Some(&(Permissions::WRITE | Permissions::DELETE)), trie.get_exact(&Acl::new("/path/to/resource")));
+
+        // Aux nodes are vacant entries
+        let value = trie.entry(Acl::new("/path/"))
+            .and_modify(|permissions| *permissions |= Permissions::DELETE)
+            .or_insert(Permissions::CREATE);
+        *value |= Permissions::READ;
+        assert_eq!(Some(&(Permissions::CREATE | Permissions::READ)), trie.get_exact(&Acl::new("/path/")));
+
+        let value = trie.entry(Acl::new("/other")).or_default();
+        assert_eq!(Permissions::empty(), *value);
+        assert_eq!(Permissions::OWNER, *trie.entry(Acl::new("/new")).or_insert_with(|| Permissions::OWNER));
+        assert_eq!("/new", trie.entry(Acl::new("/new")).key().path);
+        assert_eq!(Permissions::OWNER, trie.get::<GlobMatcher>(&Acl::new("/new")).unwrap());
+    }
+
+    #[test]
+    fn collection_test() {
+        let rules = vec![
+            (Acl::new("/path/to/resource"), Permissions::WRITE),
+            (Acl::new("/path/*"), Permissions::READ),
+            (Acl::new("/other"), Permissions::CREATE),
+        ];
+        let mut trie: AclTrie = rules.clone().into_iter().collect();
+        assert_eq!(3, trie.len());
+        assert!(!trie.is_empty());
+
+        let mut other = AclTrie::default();
+        other.extend(rules.into_iter().rev());
+        assert_eq!(trie, other);
+        other.insert(Acl::new("/other"), Permissions::READ);
+        assert_ne!(trie, other);
+        assert_eq!(
+            "{Acl { path: \"/other\" }: CREATE, Acl { path: \"/path/*\" }: READ, Acl { path: \"/path/to/resource\" }: WRITE}",
+            format!("{:?}", trie)
+        );
+
+        // Inserting an existing key or removing a missing one keeps the count
+        trie.insert(Acl::new("/path/*"), Permissions::READ);
+        trie.insert(Acl::new("/path/"), Permissions::READ);
+        assert!(trie.remove(&Acl::new("/path/to")).is_none());
+        assert_eq!(4, trie.len());
+
+        let keys: Vec<String> = trie.keys().map(|key| key.path).collect();
+        assert_eq!(vec!["/other", "/path/", "/path/*", "/path/to/resource"], keys);
+        let values: Vec<&Permissions> = trie.values().collect();
+        assert_eq!(vec![&Permissions::CREATE, &Permissions::READ, &Permissions::READ, &Permissions::WRITE], values);
+        for (key, value) in &trie {
+            assert_eq!(Some(value), trie.get_exact(&key));
+        }
+
+        let entries = trie.clone().into_iter();
+        assert_eq!(4, entries.len());
+        let keys: Vec<String> = entries.map(|(key, _)| key.path).collect();
+        assert_eq!(vec!["/other", "/path/", "/path/*", "/path/to/resource"], keys);
+
+        trie.clear();
+        assert!(trie.is_empty());
+        assert_eq!(trie, AclTrie::new());
+        assert!(trie.get::<GlobMatcher>(&Acl::new("/other")).is_none());
+        assert_eq!("{}", format!("{:?}", trie));
+    }
+
+    #[test]
+    fn iter_test() {
+        let mut trie = Trie::new();
+        for key in ["z", "aaaa", "aabb", "aacc", "a", "aa", "ab", "b"] {
+            trie.insert(String::from(key), key.len());
+        }
+        let mut iter = trie.iter();
+        assert_eq!(8, iter.len());
+        assert_eq!(Some((String::from("a"), &1)), iter.next());
+        assert_eq!(7, iter.len());
+        let keys: Vec<String> = iter.map(|(key, _)| key).collect();
+        assert_eq!(vec!["aa", "aaaa", "aabb", "aacc", "ab", "b", "z"], keys);
+
+        trie.remove(&String::from("aa"));
+        trie.remove(&String::from("a"));
+        let keys: Vec<String> = trie.keys().collect();
+        assert_eq!(vec!["aaaa", "aabb", "aacc", "ab", "b", "z"], keys);
+        assert_eq!(6, trie.values().count());
+
+        // `**` cannot split after its first `*`, yet sorts among the keys extending `*`
+        let trie: AclTrie = ["/a/*b", "/a/**", "/a/*", "/a/**c", "/a/b"].iter()
+            .map(|key| (Acl::new(key), Permissions::READ))
+            .collect();
+        let keys: Vec<String> = trie.keys().map(|key| key.path).collect();
+        assert_eq!(vec!["/a/*", "/a/**", "/a/**c", "/a/*b", "/a/b"], keys);
+        let keys: Vec<String> = trie.into_iter().map(|(key, _)| key.path).collect();
+        assert_eq!(vec!["/a/*", "/a/**", "/a/**c", "/a/*b", "/a/b"], keys);
+    }
+
+    #[test]
+    #[allow(clippy::assertions_on_constants)]
     fn serde_test() {
         let mut trie = AclTrie::new();
         trie.insert(Acl::new("aaaa"), Permissions::empty());
@@ -188,4 +667,217 @@ mod tests {
         let another_trie = bincode::deserialize::<AclTrie>(&serialized_bytes).unwrap();
         assert!(another_trie.get::<GlobMatcher>(&Acl::new("aabbcc")).is_none());
     }
+
+    #[test]
+    fn unicode_test() {
+        let mut trie = Trie::new();
+        trie.insert(String::from("/café/a"), 1);
+        trie.insert(String::from("/cafè/b"), 2);
+        trie.insert(String::from("/caf"), 3);
+        assert_eq!(Some(&1), trie.get_exact(&String::from("/café/a")));
+        assert_eq!(Some(&2), trie.get_exact(&String::from("/cafè/b")));
+        assert_eq!(vec![3, 2, 1], trie.values().copied().collect::<Vec<_>>());
+
+        let mut trie = AclTrie::new();
+        trie.insert(Acl::new("/café/*"), Permissions::READ);
+        trie.insert(Acl::new("/cafè/*"), Permissions::WRITE);
+        assert_eq!(Some(Permissions::READ), trie.get_merge::<GlobMatcher>(&Acl::new("/café/ñ")));
+        assert_eq!(4, Acl::new("/caf").key_len());
+        assert_eq!(Acl::new("é/*"), Acl::new("/café/*").new_from_postfix(4));
+    }
+
+    #[test]
+    fn borrowed_query_test() {
+        let mut trie = AclTrie::new();
+        trie.insert(Acl::new("/docs/*"), Permissions::READ);
+        trie.insert(Acl::new("/docs/drafts/*"), Permissions::WRITE);
+        let path = String::from("/docs/drafts/plan");
+        assert_eq!(Some(Permissions::READ | Permissions::WRITE), trie.get_merge::<GlobMatcher>(path.as_str()));
+        assert_eq!(trie.get_merge::<GlobMatcher>(&Acl::new(&path)), trie.get_merge::<GlobMatcher>(path.as_str()));
+        assert_eq!(Some(Permissions::READ), trie.get::<GlobMatcher>("/docs/readme"));
+        assert_eq!(vec![Acl::new("/docs/*")], trie.matches::<GlobMatcher>("/docs/x").map(|(key, _)| key).collect::<Vec<_>>());
+        assert_eq!(None, trie.get_with_key::<GlobMatcher>("/src/main.rs"));
+
+        let mut trie = Trie::new();
+        trie.insert(String::from("ab"), 1);
+        assert_eq!(Some(1), trie.get::<crate::literal::ExactMatcher>("ab"));
+
+        let mut trie: Trie<Vec<u8>, i32> = Trie::new();
+        trie.insert(vec![1, 2], 1);
+        assert_eq!(Some(1), trie.get::<crate::literal::ExactMatcher<u8>>(&[1_u8, 2][..]));
+
+        // Folding tries fold borrowed queries too
+        let mut trie = AclTrie::with_folding(crate::fold::Folding::new(true, None));
+        trie.insert(Acl::new("/Docs/*"), Permissions::READ);
+        assert_eq!(Some(Permissions::READ), trie.get_merge::<GlobMatcher>("/DOCS/x"));
+    }
+
+    /// Keys sharing many multi-byte prefixes
+    fn unicode_key() -> impl Strategy<Value = String> {
+        prop_oneof![
+            "[aé€𝄞/]{0,6}",
+            any::<String>(),
+        ]
+    }
+
+    /// Keys mixing tokens whose symbol lengths differ from their token boundaries
+    fn token_key(pieces: &'static [&'static str]) -> impl Strategy<Value = Vec<(String, u8)>> {
+        prop::collection::vec((prop::collection::vec(prop::sample::select(pieces), 0..7)
+            .prop_map(|pieces| pieces.concat()), any::<u8>()), 0..24)
+    }
+
+    /// Inserts then removes `keys`, checking exact lookups, length and iteration against a [BTreeMap]
+    fn check_exact<K>(keys: Vec<(String, u8)>, new_key: impl Fn(&str) -> K) -> Result<(), TestCaseError>
+        where K: KeyPrefix + Clone + std::fmt::Display
+    {
+        let mut trie = Trie::new();
+        let mut map = BTreeMap::new();
+        let check = |trie: &Trie<K, u8>, map: &BTreeMap<String, u8>| {
+            prop_assert_eq!(map.len(), trie.len());
+            for (key, value) in map.iter() {
+                prop_assert_eq!(Some(value), trie.get_exact(&new_key(key)), "{}", key);
+                prop_assert!(trie.contains_key(&new_key(key)), "{}", key);
+            }
+            let stored: Vec<(String, u8)> = trie.iter().map(|(key, value)| (key.to_string(), *value)).collect();
+            prop_assert_eq!(map.iter().map(|(key, value)| (key.clone(), *value)).collect::<Vec<_>>(), stored);
+            Ok(())
+        };
+        for (key, value) in keys.iter() {
+            prop_assert_eq!(map.insert(key.clone(), *value), trie.insert(new_key(key), *value), "{}", key);
+        }
+        check(&trie, &map)?;
+        for (key, _) in keys.iter().step_by(2) {
+            prop_assert_eq!(map.remove(key), trie.remove(&new_key(key)), "{}", key);
+        }
+        check(&trie, &map)
+    }
+
+    proptest! {
+        #[test]
+        fn token_keys_property_test(acls in token_key(&["a", "/", "*", "**", "?", "[", "]", "!", "-", "{", ",", "}", "\\", "\\*"]),
+                                    regexes in token_key(&["a", "1", ".", "(", ")", "|", "*", "+", "?", "{", "}", ",", "[", "]", "^", "\\"]),
+                                    mqtt_topics in token_key(&["a", "/", "+", "#", "$"]),
+                                    amqp_keys in token_key(&["a", ".", "*", "#"])) {
+            check_exact(acls, Acl::new)?;
+            check_exact(regexes, crate::regex::RegexKey::new)?;
+            check_exact(mqtt_topics, crate::topic::mqtt::MqttTopic::new)?;
+            check_exact(amqp_keys, crate::topic::amqp::AmqpRoutingKey::new)?;
+        }
+
+        #[test]
+        fn key_prefix_property_test(key in any::<String>(), index in 0..16_usize) {
+            let index = index.min(key.key_len());
+            let prefix = key.new_from_key_prefix(index);
+            let postfix = key.new_from_postfix(index);
+            prop_assert_eq!(index, prefix.key_len());
+            prop_assert_eq!(key.key_len() - index, postfix.key_len());
+            prop_assert_eq!(&key, &prefix.new_from_concat(&postfix));
+        }
+
+        #[test]
+        fn unicode_keys_property_test(keys in prop::collection::vec((unicode_key(), any::<u8>()), 0..32),
+                                      removed in prop::collection::vec(unicode_key(), 0..8)) {
+            let mut trie = Trie::new();
+            let mut map = BTreeMap::new();
+            for (key, value) in keys.iter() {
+                prop_assert_eq!(map.insert(key.clone(), *value), trie.insert(key.clone(), *value));
+            }
+            prop_assert_eq!(map.len(), trie.len());
+            for (key, value) in map.iter() {
+                prop_assert_eq!(Some(value), trie.get_exact(key));
+            }
+            prop_assert_eq!(map.iter().map(|(key, value)| (key.clone(), value)).collect::<Vec<_>>(), trie.iter().collect::<Vec<_>>());
+            for key in removed.iter().chain(keys.iter().step_by(2).map(|(key, _)| key)) {
+                prop_assert_eq!(map.remove(key), trie.remove(key));
+            }
+            prop_assert_eq!(map.iter().map(|(key, value)| (key.clone(), value)).collect::<Vec<_>>(), trie.iter().collect::<Vec<_>>());
+        }
+
+        #[test]
+        fn unicode_acl_property_test(paths in prop::collection::vec("[aé€𝄞/]{0,6}", 0..16)) {
+            let mut trie = AclTrie::new();
+            for path in paths.iter() {
+                trie.insert(Acl::new(path), Permissions::READ);
+            }
+            for path in paths.iter() {
+                prop_assert_eq!(Some(&Permissions::READ), trie.get_exact(&Acl::new(path)));
+                prop_assert_eq!(Some(Permissions::READ), trie.get::<GlobMatcher>(&Acl::new(path)));
+            }
+        }
+
+        #[test]
+        fn prefix_property_test(keys in prop::collection::vec((unicode_key(), any::<u8>()), 0..32), prefix in unicode_key()) {
+            let mut trie: Trie<String, u8> = keys.iter().cloned().collect();
+            let map: BTreeMap<String, u8> = keys.iter().cloned().collect();
+            let (starting, others): (Vec<_>, Vec<_>) = map.into_iter().partition(|(key, _)| key.starts_with(&prefix));
+            prop_assert_eq!(trie.prefixes_of(prefix.as_str()).pop(), trie.longest_prefix(prefix.as_str()));
+            prop_assert_eq!(starting.iter().map(|(key, value)| (key.clone(), value)).collect::<Vec<_>>(), trie.iter_prefix(prefix.as_str()).collect::<Vec<_>>());
+            prop_assert_eq!(starting.len(), trie.count_prefix(prefix.as_str()));
+            prop_assert_eq!(&starting, &trie.subtrie(prefix.as_str()).into_iter().collect::<Vec<_>>());
+            prop_assert_eq!(&starting, &trie.remove_prefix(prefix.as_str()).into_iter().collect::<Vec<_>>());
+            prop_assert_eq!(&others, &trie.iter().map(|(key, value)| (key, *value)).collect::<Vec<_>>());
+            prop_assert_eq!(others.len(), trie.len());
+            for (key, value) in others.iter() {
+                prop_assert_eq!(Some(value), trie.get_exact(key));
+            }
+        }
+
+        #[test]
+        fn acl_range_property_test(acls in token_key(&["a", "/", "*", "**", "?", "[", "]", "\\", "\\*"]),
+                                   start in "[a/*?b]{0,5}", end in "[a/*?b]{0,5}", back in any::<Vec<bool>>()) {
+            let mut trie = AclTrie::new();
+            let mut map = BTreeMap::new();
+            for (path, value) in acls {
+                let permissions = Permissions::from_bits_truncate(value);
+                trie.insert(Acl::new(&path), permissions);
+                map.insert(path, permissions);
+            }
+            let (start, end) = if start <= end { (start, end) } else { (end, start) };
+            fn expected<'a>(range: impl Iterator<Item = (&'a String, &'a Permissions)>) -> Vec<(String, &'a Permissions)> {
+                range.map(|(path, permissions)| (path.clone(), permissions)).collect()
+            }
+            fn found<'a>(range: impl Iterator<Item = (Acl, &'a Permissions)>) -> Vec<(String, &'a Permissions)> {
+                range.map(|(key, permissions)| (key.path, permissions)).collect()
+            }
+            let (start_key, end_key) = (Acl::new(&start), Acl::new(&end));
+            prop_assert_eq!(expected(map.range(start.clone()..end.clone())), found(trie.range(start_key.clone()..end_key.clone())));
+            prop_assert_eq!(expected(map.range(start.clone()..=end.clone())), found(trie.range(start_key.clone()..=end_key.clone())));
+            prop_assert_eq!(expected(map.range(..=end.clone()).rev()), found(trie.range(..=end_key).rev()));
+            prop_assert_eq!(expected(map.iter().next_back().into_iter()), found(trie.last().into_iter()));
+            prop_assert_eq!(expected(map.range(..start.clone()).next_back().into_iter()), found(trie.predecessor(start.as_str()).into_iter()));
+            prop_assert_eq!(expected(map.range(start.clone()..).find(|(path, _)| **path != start).into_iter()), found(trie.successor(start.as_str()).into_iter()));
+
+            // Alternating ends
+            let mut expected = map.range(start..);
+            let mut range = trie.range(start_key..);
+            for back in back.into_iter().chain(std::iter::repeat(false).take(map.len() + 1)) {
+                let (expected, found) = if back { (expected.next_back(), range.next_back()) } else { (expected.next(), range.next()) };
+                prop_assert_eq!(expected.map(|(path, permissions)| (path.clone(), permissions)), found.map(|(key, permissions)| (key.path, permissions)));
+            }
+        }
+
+        #[test]
+        fn range_property_test(keys in prop::collection::vec((unicode_key(), any::<u8>()), 0..32),
+                               start in unicode_key(), end in unicode_key(), back in any::<Vec<bool>>()) {
+            let trie: Trie<String, u8> = keys.iter().cloned().collect();
+            let map: BTreeMap<String, u8> = keys.iter().cloned().collect();
+            let (start, end) = if start <= end { (start, end) } else { (end, start) };
+            fn expected<'a>(range: impl Iterator<Item = (&'a String, &'a u8)>) -> Vec<(String, &'a u8)> {
+                range.map(|(key, value)| (key.clone(), value)).collect()
+            }
+            prop_assert_eq!(expected(map.range(start.clone()..end.clone())), trie.range(start.clone()..end.clone()).collect::<Vec<_>>());
+            prop_assert_eq!(expected(map.range(start.clone()..=end.clone())), trie.range(start.clone()..=end.clone()).collect::<Vec<_>>());
+            prop_assert_eq!(expected(map.range(..=end.clone())).into_iter().rev().collect::<Vec<_>>(), trie.range(..=end.clone()).rev().collect::<Vec<_>>());
+            prop_assert_eq!(map.range(..start.clone()).next_back().map(|(key, value)| (key.clone(), value)), trie.predecessor(start.as_str()));
+            prop_assert_eq!(map.range(start.clone()..).find(|(key, _)| **key != start).map(|(key, value)| (key.clone(), value)), trie.successor(start.as_str()));
+
+            // Alternating ends
+            let mut expected = map.range(start.clone()..);
+            let mut range = trie.range(start..);
+            for back in back.into_iter().chain(std::iter::repeat(false).take(map.len() + 1)) {
+                let (expected, found) = if back { (expected.next_back(), range.next_back()) } else { (expected.next(), range.next()) };
+                prop_assert_eq!(expected.map(|(key, value)| (key.clone(), value)), found);
+            }
+        }
+    }
 }
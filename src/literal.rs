@@ -0,0 +1,254 @@
+//! Literal matchers, for keys of any alphabet (e.g. binary keys)
+//!
+//! Key tokens are taken as literals, and the match key is only consumed along the stored keys,
+//! so subtrees diverging from the match key are pruned right away.
+use std::sync::Arc;
+use crate::matcher::{Event, PushdownStateMachine, State, StateSequence};
+
+#[derive(Debug, Clone)]
+struct Literal<S> {
+    /// Stored key symbols stepped in so far
+    key: Vec<S>,
+    /// Match key symbols fed so far
+    query: Vec<S>,
+    end_of_stream: bool,
+}
+
+impl<S: Clone + Eq> Literal<S> {
+
+    #[inline]
+    fn new() -> Self {
+        Self {
+            key: Vec::new(),
+            query: Vec::new(),
+            end_of_stream: false,
+        }
+    }
+
+    /// Whether the stored key and the match key agree so far
+    #[inline]
+    fn agrees(&self) -> bool {
+        let common = std::cmp::min(self.key.len(), self.query.len());
+        self.key[..common] == self.query[..common]
+    }
+
+    #[inline]
+    fn accepts_more(&self) -> bool {
+        !self.end_of_stream && self.query.len() < self.key.len() && self.agrees()
+    }
+
+    #[inline]
+    fn feed(&mut self, ev: Event<S>) {
+        match ev {
+            Event::CharIn(symbol) => self.query.push(symbol),
+            Event::EndOfStream => self.end_of_stream = true,
+        }
+    }
+
+    /// State of a matcher accepting either the stored keys the match key starts with (`prefix`),
+    /// or the stored keys equal to the match key
+    #[inline]
+    fn state(&self, prefix: bool) -> State {
+        if !self.agrees() {
+            State::Rejected
+        }
+        else if self.key.len() > self.query.len() {
+            if self.end_of_stream { State::Rejected } else { State::Expecting }
+        }
+        else if prefix || (self.end_of_stream && self.key.len() == self.query.len()) {
+            State::Accepted
+        }
+        else {
+            // Deeper keys may still match
+            State::Expecting
+        }
+    }
+}
+
+/// Stack of literal states, one per trie level
+#[derive(Debug, Clone)]
+struct LiteralStack<S> {
+    stack: Vec<Literal<S>>,
+}
+
+impl<S: Clone + Eq> LiteralStack<S> {
+
+    #[inline]
+    #[allow(clippy::ptr_arg)]
+    fn step_in(&mut self, sequence: &Vec<Arc<StateSequence<S>>>) {
+        let mut literal = self.stack.last().cloned().unwrap_or_else(Literal::new);
+        literal.key.extend(sequence.iter().flat_map(|token| token.sequence.iter().cloned()));
+        self.stack.push(literal);
+    }
+
+    #[inline]
+    fn accepts_more(&self) -> bool {
+        self.stack.last().is_some_and(Literal::accepts_more)
+    }
+
+    #[inline]
+    fn feed(&mut self, ev: Event<S>) {
+        if let Some(literal) = self.stack.last_mut() {
+            literal.feed(ev)
+        }
+    }
+
+    #[inline]
+    fn state(&self, prefix: bool) -> State {
+        match self.stack.last() {
+            Some(literal) => literal.state(prefix),
+            None => State::Failure(String::from("Machine not initiallized"))
+        }
+    }
+}
+
+/// Accepts the stored keys equal to the match key
+#[derive(Debug, Clone)]
+pub struct ExactMatcher<S = char> {
+    inner: LiteralStack<S>,
+}
+
+/// Accepts the stored keys the match key starts with
+#[derive(Debug, Clone)]
+pub struct PrefixMatcher<S = char> {
+    inner: LiteralStack<S>,
+}
+
+impl<S: Clone + Eq> PushdownStateMachine<S> for ExactMatcher<S> {
+    fn new() -> Self {
+        Self {
+            inner: LiteralStack { stack: Vec::new() },
+        }
+    }
+
+    #[inline]
+    fn step_in(&mut self, sequence: &Vec<Arc<StateSequence<S>>>) {
+        self.inner.step_in(sequence)
+    }
+
+    #[inline]
+    fn step_out(&mut self) {
+        let _k = self.inner.stack.pop();
+    }
+
+    #[inline]
+    fn accepts_more(&self) -> bool {
+        self.inner.accepts_more()
+    }
+
+    #[inline]
+    fn feed(&mut self, ev: Event<S>) {
+        self.inner.feed(ev)
+    }
+
+    #[inline]
+    fn state(&self) -> State {
+        self.inner.state(false)
+    }
+
+    #[inline]
+    fn is_sink(&self) -> bool {
+        !matches!(self.state(), State::Accepting | State::Expecting)
+    }
+}
+
+impl<S: Clone + Eq> PushdownStateMachine<S> for PrefixMatcher<S> {
+    fn new() -> Self {
+        Self {
+            inner: LiteralStack { stack: Vec::new() },
+        }
+    }
+
+    #[inline]
+    fn step_in(&mut self, sequence: &Vec<Arc<StateSequence<S>>>) {
+        self.inner.step_in(sequence)
+    }
+
+    #[inline]
+    fn step_out(&mut self) {
+        let _k = self.inner.stack.pop();
+    }
+
+    #[inline]
+    fn accepts_more(&self) -> bool {
+        self.inner.accepts_more()
+    }
+
+    #[inline]
+    fn feed(&mut self, ev: Event<S>) {
+        self.inner.feed(ev)
+    }
+
+    #[inline]
+    fn state(&self) -> State {
+        self.inner.state(true)
+    }
+
+    #[inline]
+    fn is_sink(&self) -> bool {
+        !matches!(self.state(), State::Accepting | State::Expecting)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+    use crate::trie::Trie;
+    use crate::levenshtein::LevenshteinMatcher;
+    use crate::literal::*;
+
+    #[test]
+    fn byte_keys_test() {
+        let mut trie: Trie<Vec<u8>, &str> = Trie::new();
+        trie.insert(vec![0xde, 0xad, 0xbe, 0xef], "deadbeef");
+        trie.insert(vec![0xde, 0xad], "dead");
+        trie.insert(vec![0xff, 0xfe, 0x00], "invalid utf-8");
+        trie.insert(vec![0xde, 0xaf], "deaf");
+        trie.insert(Vec::new(), "empty");
+
+        assert_eq!(Some(&"dead"), trie.get_exact(&vec![0xde, 0xad]));
+        assert_eq!(None, trie.get_exact(&vec![0xde]));
+        assert_eq!(vec!["empty", "dead", "deadbeef", "deaf", "invalid utf-8"], trie.values().copied().collect::<Vec<_>>());
+        assert_eq!(Some("deadbeef"), trie.get::<ExactMatcher<u8>>(&vec![0xde, 0xad, 0xbe, 0xef]));
+        assert_eq!(None, trie.get::<ExactMatcher<u8>>(&vec![0xde, 0xad, 0xbe]));
+
+        let prefixes = |trie: &Trie<Vec<u8>, &'static str>, key: &[u8]| trie.matches::<PrefixMatcher<u8>>(&key.to_vec())
+            .map(|(_, value)| *value)
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["empty", "dead", "deadbeef"], prefixes(&trie, &[0xde, 0xad, 0xbe, 0xef, 0x01]));
+        assert_eq!(vec!["empty", "dead"], prefixes(&trie, &[0xde, 0xad, 0xbe]));
+        assert_eq!(vec!["empty"], prefixes(&trie, &[0xff, 0xfe]));
+
+        let close: Vec<&str> = trie.matches::<LevenshteinMatcher<1, false, u8>>(&vec![0xde, 0xa0])
+            .map(|(_, value)| *value)
+            .collect();
+        assert_eq!(vec!["dead", "deaf"], close);
+
+        assert_eq!(Some("dead"), trie.remove(&vec![0xde, 0xad]));
+        assert_eq!(Some(&"deadbeef"), trie.get_exact(&vec![0xde, 0xad, 0xbe, 0xef]));
+
+        let mut trie: Trie<Box<[u8]>, i32> = Trie::new();
+        trie.insert(vec![1, 2, 3].into_boxed_slice(), 1);
+        trie.insert(vec![1, 2, 4].into_boxed_slice(), 2);
+        assert_eq!(Some(&2), trie.get_exact(&vec![1_u8, 2, 4].into_boxed_slice()));
+        assert_eq!(Some(1), trie.remove(&vec![1_u8, 2, 3].into_boxed_slice()));
+        assert_eq!(vec![vec![1, 2, 4].into_boxed_slice()], trie.keys().collect::<Vec<_>>());
+
+        let bytes = [7_u8, 8, 9];
+        let mut trie: Trie<Cow<[u8]>, i32> = Trie::new();
+        trie.insert(Cow::Borrowed(&bytes[..]), 1);
+        trie.insert(Cow::Borrowed(&bytes[..2]), 2);
+        assert_eq!(vec![2, 1], trie.matches::<PrefixMatcher<u8>>(&Cow::Borrowed(&bytes[..])).map(|(_, value)| *value).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn literal_matchers_test() {
+        let trie: Trie<String, i32> = vec![(String::from("a"), 1), (String::from("ab"), 2), (String::from("abc"), 3), (String::from("b"), 4)]
+            .into_iter()
+            .collect();
+        assert_eq!(Some(2), trie.get::<ExactMatcher>(&String::from("ab")));
+        assert_eq!(None, trie.get::<ExactMatcher>(&String::from("abd")));
+        assert_eq!(vec![1, 2], trie.matches::<PrefixMatcher>(&String::from("abd")).map(|(_, value)| *value).collect::<Vec<_>>());
+        assert_eq!(Vec::<i32>::new(), trie.matches::<PrefixMatcher>(&String::new()).map(|(_, value)| *value).collect::<Vec<_>>());
+    }
+}
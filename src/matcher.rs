@@ -7,7 +7,93 @@ use serde::{Serialize, Deserialize};
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum MatchType {
     Literal,
-    AnyOr
+    AnyOr,
+    /// Like [MatchType::AnyOr], but crossing segment separators on segment aware matchers
+    DeepAnyOr,
+    AnyChar,
+    Class(CharClass),
+    Alternation(Vec<Vec<char>>),
+    /// A whole level: any chars but `separator`, the first one not being `excluded`
+    AnyLevel { separator: char, excluded: Option<char> },
+    /// Zero or more whole levels, along with the `separator` starting the literal tail if any.
+    /// The first char is not `excluded`
+    AnyLevels { separator: char, excluded: Option<char> },
+    /// A `separator` followed by zero or more whole levels, or nothing at all
+    SubLevels { separator: char },
+    /// A regex construct
+    Regex(RegexNode),
+}
+
+/// A restricted regex syntax tree
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum RegexNode {
+    Char(char),
+    Any,
+    Class(CharClass),
+    /// Alternative sequences
+    Group(Vec<Vec<RegexNode>>),
+    /// Greedy repetition, unbounded without `max`
+    Repeat { node: Box<RegexNode>, min: usize, max: Option<usize> },
+}
+
+/// A set of char ranges, possibly negated
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CharClass {
+    pub negated: bool,
+    pub ranges: Vec<(char, char)>,
+}
+
+impl CharClass {
+
+    #[inline]
+    pub fn matches(&self, ch: char) -> bool {
+        self.ranges.iter().any(|(low, high)| *low <= ch && ch <= *high) != self.negated
+    }
+
+    /// Parses a class following `[`, negated by a leading `!` or `^`, returning it along with the consumed chars (`]` included)
+    pub fn parse(chars: &[char]) -> Option<(CharClass, usize)> {
+        let mut ranges = Vec::new();
+        let (negated, len) = Self::scan(chars.iter().copied(), |low, high| ranges.push((low, high)))?;
+        Some((Self { negated, ranges }, len))
+    }
+
+    /// Scans a class following `[` as [CharClass::parse] does, handing its ranges to `on_range`
+    /// rather than collecting them. Returns whether it is negated along with the consumed chars
+    pub(crate) fn scan<I>(mut chars: I, mut on_range: impl FnMut(char, char)) -> Option<(bool, usize)>
+        where I: Iterator<Item = char> + Clone
+    {
+        let mut position = 0;
+        let negated = matches!(chars.clone().next(), Some('!') | Some('^'));
+        if negated {
+            chars.next();
+            position += 1;
+        }
+        let first = position;
+        loop {
+            let mut low = chars.next()?;
+            if low == ']' && position > first {
+                return Some((negated, position + 1));
+            }
+            if low == '\\' {
+                position += 1;
+                low = chars.next()?;
+            }
+            position += 1;
+            let mut high = low;
+            let mut ahead = chars.clone();
+            if ahead.next() == Some('-') && !matches!(ahead.next(), None | Some(']')) {
+                chars.next();
+                position += 1;
+                high = chars.next()?;
+                if high == '\\' {
+                    position += 1;
+                    high = chars.next()?;
+                }
+                position += 1;
+            }
+            on_range(low, high);
+        }
+    }
 }
 
 ///////////////////////////
@@ -22,33 +108,141 @@ pub enum State {
     Failure(String),
 }
 
+/// Match key input, in the key alphabet
 #[derive(Debug, Clone, Copy)]
-pub enum Event {
-    CharIn(char),
+pub enum Event<S = char> {
+    CharIn(S),
     EndOfStream
 }
 
+/// The span of the query consumed by a wildcard, in char offsets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capture {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Capture {
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// The captured substring of `query`
+    pub fn slice<'a>(&self, query: &'a str) -> &'a str {
+        let mut bounds = query.char_indices()
+            .map(|(index, _)| index)
+            .chain(std::iter::once(query.len()));
+        let start = bounds.nth(self.start).unwrap_or(query.len());
+        let end = if self.is_empty() { Some(start) } else { bounds.nth(self.len() - 1) };
+        &query[start..end.unwrap_or(query.len())]
+    }
+}
+
+/// A key token: an optional leading construct (as told by `match_type`) followed by a literal `sequence`
+/// of symbols of the key alphabet. Constructs are textual, so non `char` keys only hold literals
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
-pub struct StateSequence {
+pub struct StateSequence<S = char> {
     pub match_type: MatchType,
-    pub sequence: Vec<char>
+    pub sequence: Vec<S>
+}
+
+/// Compiles a key token by token: literal chars get gathered into the sequence following the
+/// last construct
+pub(crate) struct SequenceBuilder {
+    compiled: Vec<Arc<StateSequence>>,
+    match_type: MatchType,
+    sequence: Vec<char>,
 }
 
-///! This is the trait on which [Iterator] relies
-///!
-pub trait PushdownStateMachine {
+impl SequenceBuilder {
+
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            compiled: Vec::new(),
+            match_type: MatchType::Literal,
+            sequence: Vec::new(),
+        }
+    }
+
+    #[inline]
+    pub fn literal(&mut self, ch: char) {
+        self.sequence.push(ch);
+    }
+
+    #[inline]
+    pub fn literals(&mut self, chars: &[char]) {
+        self.sequence.extend_from_slice(chars);
+    }
+
+    /// Takes back the last literal char, if any
+    #[inline]
+    pub fn pop_literal(&mut self) -> Option<char> {
+        self.sequence.pop()
+    }
+
+    /// Starts a new token with the `match_type` construct
+    #[inline]
+    pub fn construct(&mut self, match_type: MatchType) {
+        self.flush();
+        self.match_type = match_type;
+    }
+
+    #[inline]
+    pub fn build(mut self) -> Vec<Arc<StateSequence>> {
+        self.flush();
+        self.compiled
+    }
+
+    /// Ends the current token, unless empty
+    #[inline]
+    fn flush(&mut self) {
+        if !self.sequence.is_empty() || self.match_type != MatchType::Literal {
+            self.compiled.push(Arc::new(StateSequence {
+                match_type: std::mem::replace(&mut self.match_type, MatchType::Literal),
+                sequence: std::mem::take(&mut self.sequence),
+            }));
+        }
+    }
+}
+
+/// This is the trait on which [Iterator] relies, matching keys of the `S` alphabet
+///
+pub trait PushdownStateMachine<S = char> {
     fn new() -> Self;
 
-    fn step_in(&mut self, key: &Vec<Arc<StateSequence>>);
+    #[allow(clippy::ptr_arg)]
+    fn step_in(&mut self, key: &Vec<Arc<StateSequence<S>>>);
     fn step_out(&mut self);
 
     fn accepts_more(&self) -> bool;
-    fn feed(&mut self, ev: Event);
+    fn feed(&mut self, ev: Event<S>);
 
     fn state(&self) -> State;
     fn is_sink(&self) -> bool;
+
+    /// Spans of the query consumed by each wildcard of an accepted key
+    #[inline]
+    fn captures(&self) -> Vec<Capture> {
+        Vec::new()
+    }
+
+    /// Cost of an accepted key, such as its edit distance, for matchers ranking their matches
+    #[inline]
+    fn cost(&self) -> usize {
+        0
+    }
 }
 
+/// Left over from lookahead matching: no matcher uses it, captures are reported by [Capture] instead
+#[deprecated(note = "unused by the matchers, see `PushdownStateMachine::captures`")]
 pub enum Ahead {
     Exactly(char),
     AnyOr(char),
@@ -62,7 +256,7 @@ mod tests {
     #[test]
     fn matcher_test() {
         let ev1 = Event::CharIn(12 as char);
-        let ev2 = Event::EndOfStream;
+        let ev2: Event = Event::EndOfStream;
         let ev3 = ev2;
         println!("states = {:?}, {:?} {:?}", ev1.clone(), ev2, ev3);
     }
@@ -0,0 +1,329 @@
+//! A tiny Pike-like virtual machine backing the pattern matchers.
+//!
+//! Programs grow while the matcher steps into trie nodes, so threads running past the end
+//! of the program are parked along with the input offset they reached, and resumed over the
+//! recorded input as soon as more instructions are appended.
+use std::collections::{HashMap, VecDeque};
+use std::collections::hash_map::Entry;
+use crate::matcher::{Capture, CharClass};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inst {
+    /// Consumes exactly this char
+    Char(char),
+    /// Consumes any char
+    Any,
+    /// Consumes any char but this one
+    AnyBut(char),
+    /// Consumes any char in the class
+    Class(CharClass),
+    /// Forks the thread, the first branch being preferred
+    Split(usize, usize),
+    /// Jumps to the given instruction
+    Jump(usize),
+    /// Opens a capture at the current offset
+    Open,
+    /// Closes the last opened capture at the current offset
+    Close,
+}
+
+#[derive(Debug, Clone)]
+struct Thread {
+    pc: usize,
+    captures: Vec<Capture>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Vm {
+    program: Vec<Inst>,
+    input: Vec<char>,
+    /// Threads waiting on a consuming instruction at the end of the input
+    threads: Vec<Thread>,
+    /// Threads which ran past the end of the program, along with the input offset they reached
+    parked: Vec<(usize, Thread)>,
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Vm {
+
+    /// Creates a machine with an empty program, so its single thread is parked at offset 0
+    pub fn new() -> Self {
+        Self {
+            program: Vec::new(),
+            input: Vec::new(),
+            threads: Vec::new(),
+            parked: vec![(0, Thread { pc: 0, captures: Vec::new() })],
+        }
+    }
+
+    /// Current program length, which is the base address for the next appended instructions
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.program.len()
+    }
+
+    /// Appends instructions to the program, resuming the parked threads over the recorded input
+    pub fn extend(&mut self, insts: Vec<Inst>) {
+        self.program.extend(insts);
+        let mut parked = std::mem::take(&mut self.parked);
+        parked.sort_by_key(|(offset, _)| *offset);
+        let mut pending = parked.into_iter().peekable();
+        let first_offset = match pending.peek() {
+            None => return,
+            Some((offset, _)) => *offset,
+        };
+
+        let mut current = Vec::new();
+        let mut seen = vec![false; self.program.len()];
+        for offset in first_offset..=self.input.len() {
+            while let Some((_, thread)) = pending.next_if(|(parked_at, _)| *parked_at == offset) {
+                self.add(&mut current, &mut seen, thread, offset);
+            }
+            if offset == self.input.len() {
+                break;
+            }
+            current = self.step(current, offset);
+            seen = vec![false; self.program.len()];
+        }
+        for thread in current {
+            if !self.threads.iter().any(|waiting| waiting.pc == thread.pc) {
+                self.threads.push(thread);
+            }
+        }
+    }
+
+    /// Feeds the next input char
+    pub fn feed(&mut self, ch: char) {
+        let offset = self.input.len();
+        self.input.push(ch);
+        let threads = std::mem::take(&mut self.threads);
+        self.threads = self.step(threads, offset);
+    }
+
+    /// Notifies the end of the input: waiting threads cannot succeed anymore
+    #[inline]
+    pub fn finish(&mut self) {
+        self.threads.clear();
+    }
+
+    /// Whether some thread is waiting for more input
+    #[inline]
+    pub fn accepts_more(&self) -> bool {
+        !self.threads.is_empty()
+    }
+
+    /// Whether some thread is still alive, either waiting for input or for more instructions
+    #[inline]
+    pub fn is_alive(&self) -> bool {
+        !self.threads.is_empty() || !self.parked.is_empty()
+    }
+
+    /// Captures of the preferred thread matching the whole input with the whole program, if any
+    #[inline]
+    pub fn accepted(&self) -> Option<&[Capture]> {
+        self.parked.iter()
+            .find(|(offset, _)| *offset == self.input.len())
+            .map(|(_, thread)| thread.captures.as_slice())
+    }
+
+    /// Consumes `input[offset]` on every thread
+    fn step(&mut self, threads: Vec<Thread>, offset: usize) -> Vec<Thread> {
+        let ch = self.input[offset];
+        let mut next = Vec::new();
+        let mut seen = vec![false; self.program.len()];
+        for thread in threads {
+            let consumed = match &self.program[thread.pc] {
+                Inst::Char(expected) => *expected == ch,
+                Inst::Any => true,
+                Inst::AnyBut(excluded) => *excluded != ch,
+                Inst::Class(class) => class.matches(ch),
+                _ => false,
+            };
+            if consumed {
+                let pc = thread.pc + 1;
+                self.add(&mut next, &mut seen, Thread { pc, captures: thread.captures }, offset + 1);
+            }
+        }
+        next
+    }
+
+    /// Follows the epsilon transitions of `thread` at `offset`
+    fn add(&mut self, threads: &mut Vec<Thread>, seen: &mut Vec<bool>, mut thread: Thread, offset: usize) {
+        if thread.pc == self.program.len() {
+            if !self.parked.iter().any(|(parked_at, _)| *parked_at == offset) {
+                self.parked.push((offset, thread));
+            }
+            return;
+        }
+        if seen[thread.pc] {
+            return;
+        }
+        seen[thread.pc] = true;
+        match self.program[thread.pc] {
+            Inst::Split(preferred, other) => {
+                let forked = Thread { pc: other, captures: thread.captures.clone() };
+                thread.pc = preferred;
+                self.add(threads, seen, thread, offset);
+                self.add(threads, seen, forked, offset);
+            }
+            Inst::Jump(target) => {
+                thread.pc = target;
+                self.add(threads, seen, thread, offset);
+            }
+            Inst::Open => {
+                thread.pc += 1;
+                thread.captures.push(Capture { start: offset, end: offset });
+                self.add(threads, seen, thread, offset);
+            }
+            Inst::Close => {
+                thread.pc += 1;
+                if let Some(capture) = thread.captures.last_mut() {
+                    capture.end = offset;
+                }
+                self.add(threads, seen, thread, offset);
+            }
+            Inst::Char(_) | Inst::Any | Inst::AnyBut(_) | Inst::Class(_) => {
+                if !threads.iter().any(|waiting| waiting.pc == thread.pc) {
+                    threads.push(thread);
+                }
+            }
+        }
+    }
+}
+
+/// Program counters of a thread of each program
+type ThreadPair = (usize, usize);
+
+/// Shortest input matched by both programs, if any, searched breadth first over pairs of threads
+/// (the product automaton). Each step consumes a char both instructions accept
+pub fn intersection(first: &[Inst], second: &[Inst]) -> Option<String> {
+    // Each pair of threads along with the pair it was reached from, and the char consumed
+    let mut parents: HashMap<ThreadPair, Option<(ThreadPair, char)>> = HashMap::new();
+    let mut queue = VecDeque::new();
+    for pair in product(&closure(first, 0), &closure(second, 0)) {
+        if parents.insert(pair, None).is_none() {
+            queue.push_back(pair);
+        }
+    }
+    while let Some((pc1, pc2)) = queue.pop_front() {
+        if pc1 == first.len() && pc2 == second.len() {
+            let mut witness = Vec::new();
+            let mut pair = (pc1, pc2);
+            while let Some((parent, ch)) = parents[&pair] {
+                witness.push(ch);
+                pair = parent;
+            }
+            return Some(witness.into_iter().rev().collect());
+        }
+        if pc1 == first.len() || pc2 == second.len() {
+            continue;
+        }
+        let ch = match common_char(&first[pc1], &second[pc2]) {
+            Some(ch) => ch,
+            None => continue,
+        };
+        for pair in product(&closure(first, pc1 + 1), &closure(second, pc2 + 1)) {
+            if let Entry::Vacant(entry) = parents.entry(pair) {
+                entry.insert(Some(((pc1, pc2), ch)));
+                queue.push_back(pair);
+            }
+        }
+    }
+    None
+}
+
+/// Consuming instructions (or the end of the program) reached from `pc` through epsilon transitions
+fn closure(program: &[Inst], pc: usize) -> Vec<usize> {
+    let mut reached = Vec::new();
+    let mut seen = vec![false; program.len() + 1];
+    let mut stack = vec![pc];
+    while let Some(pc) = stack.pop() {
+        if std::mem::replace(&mut seen[pc], true) {
+            continue;
+        }
+        match program.get(pc) {
+            Some(Inst::Split(preferred, other)) => {
+                stack.push(*other);
+                stack.push(*preferred);
+            }
+            Some(Inst::Jump(target)) => stack.push(*target),
+            Some(Inst::Open | Inst::Close) => stack.push(pc + 1),
+            _ => reached.push(pc),
+        }
+    }
+    reached
+}
+
+#[inline]
+fn product(first: &[usize], second: &[usize]) -> Vec<(usize, usize)> {
+    first.iter().flat_map(|pc1| second.iter().map(move |pc2| (*pc1, *pc2))).collect()
+}
+
+/// A char consumed by both instructions, preferably an alphanumeric one
+fn common_char(first: &Inst, second: &Inst) -> Option<char> {
+    let (first, second) = (consumed(first), consumed(second));
+    let mut common = Vec::new();
+    let (mut index1, mut index2) = (0, 0);
+    while index1 < first.len() && index2 < second.len() {
+        let (low1, high1) = first[index1];
+        let (low2, high2) = second[index2];
+        if low1.max(low2) <= high1.min(high2) {
+            common.push((low1.max(low2), high1.min(high2)));
+        }
+        if high1 < high2 {
+            index1 += 1;
+        }
+        else {
+            index2 += 1;
+        }
+    }
+    ('a'..='z').chain('0'..='9')
+        .find(|ch| common.iter().any(|(low, high)| (*low..=*high).contains(&u32::from(*ch))))
+        .or_else(|| common.iter().find_map(|(low, high)| {
+            // Skipping surrogates
+            let low = if (0xD800..=0xDFFF).contains(low) { 0xE000 } else { *low };
+            char::from_u32(low).filter(|_| low <= *high)
+        }))
+}
+
+/// Chars consumed by an instruction, as sorted disjoint ranges of code points
+fn consumed(inst: &Inst) -> Vec<(u32, u32)> {
+    let (negated, mut ranges) = match inst {
+        Inst::Char(ch) => (false, vec![(u32::from(*ch), u32::from(*ch))]),
+        Inst::Any => (true, Vec::new()),
+        Inst::AnyBut(ch) => (true, vec![(u32::from(*ch), u32::from(*ch))]),
+        Inst::Class(class) => (class.negated, class.ranges.iter()
+            .filter(|(low, high)| low <= high)
+            .map(|(low, high)| (u32::from(*low), u32::from(*high)))
+            .collect()),
+        _ => (false, Vec::new()),
+    };
+    ranges.sort_unstable();
+    let mut merged: Vec<(u32, u32)> = Vec::new();
+    for (low, high) in ranges {
+        match merged.last_mut() {
+            Some((_, last_high)) if low <= last_high.saturating_add(1) => *last_high = high.max(*last_high),
+            _ => merged.push((low, high)),
+        }
+    }
+    if !negated {
+        return merged;
+    }
+    let mut complement = Vec::new();
+    let mut next = 0;
+    for (low, high) in merged {
+        if low > next {
+            complement.push((next, low - 1));
+        }
+        next = high + 1;
+    }
+    if next <= u32::from(char::MAX) {
+        complement.push((next, u32::from(char::MAX)));
+    }
+    complement
+}
@@ -3,9 +3,9 @@ use std::fmt::Debug;
 use std::marker::PhantomData;
 use std::slice::Iter;
 use serde::{Serialize, Deserialize};
-use crate::key::{TrieKey, KeyPrefix, ValueMerge};
-use crate::iterator::{TrieIterator};
-use crate::matcher::PushdownStateMachine;
+use crate::key::{advance, TrieKey, KeyPrefix, ValueMerge};
+use crate::iterator::{cmp_keys, TrieIterator};
+use crate::matcher::{Event, PushdownStateMachine, State};
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct RFRNode<K: KeyPrefix + Clone, V: Clone> {
@@ -23,6 +23,12 @@ pub struct RFRNode<K: KeyPrefix + Clone, V: Clone> {
     _phantom_v: PhantomData<V>,
 }
 
+impl<K, V> Default for RFRNode<K, V> where K: KeyPrefix + Clone, V: Clone {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<K, V> RFRNode<K, V> where K: KeyPrefix + Clone, V: Clone {
 
     #[inline]
@@ -59,127 +65,407 @@ impl<K, V> RFRNode<K, V> where K: KeyPrefix + Clone, V: Clone {
     }
 
     #[inline]
-    pub fn strip_prefix(&mut self, prefix_len: usize) {
-        self.node_key = TrieKey::new(self.node_key.key.new_from_key_prefix(prefix_len));
+    pub fn insert(&mut self, key: TrieKey<K>, value: Option<V>) -> Option<V>  {
+        self.insert_from(&key, 0, key.key.symbols(), value)
     }
 
-    #[inline]
-    pub fn insert(&mut self, key: TrieKey<K>, value: Option<V>) -> Option<V>  {
-        
-        let mut prev_insert_index = 0 as usize;
-        let mut insert_index = 0 as usize;
-        let mut lcp = 0 as usize;
-        let mut index = 0 as usize;
-        let mut full_match = false;
-
-        for child in self.children.iter() {
-            let (item_lcp, item_is_preceeding, item_is_full_match) = key.lcp(&child.node_key);
-            prev_insert_index = index;
-            lcp = item_lcp;
-            full_match = item_is_full_match;
-            if !item_is_preceeding {
-                index += 1;
-                insert_index = index;
-                if lcp > 0 {
+    /// Inserts the postfix of `key` starting at `offset` (`rest` being its symbols).
+    /// Only the nodes getting created build their keys
+    fn insert_from<I>(&mut self, key: &TrieKey<K>, offset: usize, mut rest: I, value: Option<V>) -> Option<V>
+        where I: Iterator<Item = K::Symbol> + Clone
+    {
+        match self.find_child(key, offset, &rest) {
+            ChildPosition::Shared { index, full_match: true, .. } => {
+                // Node already exists and is a full match
+                // Alternatives:
+                // 1. Colliding node is leaf -> Just replace
+                // 2. Colliding node is aux -> Turn it into a leaf
+                std::mem::replace(&mut self.children.get_mut(index).unwrap().value, value)
+            }
+            ChildPosition::Shared { index, lcp, .. } if lcp == self.children[index].node_key.key.key_len() => {
+                advance(&mut rest, lcp);
+                self.children.get_mut(index).unwrap().insert_from(key, offset + lcp, rest, value)
+            }
+            ChildPosition::Shared { index, lcp, .. } => {
+                // Partial collision: split the child under an aux node holding the common prefix
+                let mut prev_node = self.children.remove(index);
+                let mut aux = Box::new(RFRNode::new_aux(TrieKey::new(prev_node.node_key.key.new_from_key_prefix(lcp))));
+                prev_node.node_key = TrieKey::new(prev_node.node_key.key.new_from_postfix(lcp));
+                aux.children.push(prev_node);
+                advance(&mut rest, lcp);
+                if rest.clone().next().is_none() {
+                    aux.value = value;
+                }
+                else {
+                    aux.insert_from(key, offset + lcp, rest, value);
+                }
+                // The common prefix may sort apart from the child, among siblings sharing no boundary with it
+                let index = self.sorted_index(&aux.node_key.key);
+                self.children.insert(index, aux);
+                None
+            }
+            ChildPosition::Vacant(index) => {
+                // No collition -> Just insert as a leaf
+                let node_key = TrieKey::new(key.key.new_from_postfix(offset));
+                let node = match value {
+                    Some(leaf_value) => RFRNode::new_leaf_with_prefix(node_key, leaf_value),
+                    None => RFRNode::new_aux(node_key),
+                };
+                self.children.insert(index, Box::new(node));
+                None
+            }
+        }
+    }
+
+    /// Finds the child sharing a prefix boundary with the postfix of `key` starting at `offset`
+    /// (`rest` being its symbols), or else where the postfix would be inserted.
+    ///
+    /// Children are sorted and no two of them share a boundary, but pattern keys may share leading
+    /// symbols without sharing a boundary (e.g. `[a` and `[!a]`): all the children starting with the
+    /// leading symbol of the postfix get compared
+    fn find_child<I>(&self, key: &TrieKey<K>, offset: usize, rest: &I) -> ChildPosition
+        where I: Iterator<Item = K::Symbol> + Clone
+    {
+        let mut insert_index = None;
+        for (index, child) in self.children.iter().enumerate() {
+            let (lcp, shared, preceeding, full_match) = key.lcp_from(offset, rest.clone(), &child.node_key);
+            if full_match || lcp > 0 {
+                return ChildPosition::Shared { index, lcp, full_match };
+            }
+            if preceeding {
+                insert_index.get_or_insert(index);
+                if !shared {
                     break;
                 }
             }
-            else {
-                break;
+        }
+        ChildPosition::Vacant(insert_index.unwrap_or(self.children.len()))
+    }
+
+    /// Index of the first child sorting after `key`
+    #[inline]
+    fn sorted_index(&self, key: &K) -> usize {
+        self.children.partition_point(|child| child.node_key.key.symbols().lt(key.symbols()))
+    }
+
+    /// Locates the node stored under the exact `key` (literally compared, no matcher involved).
+    /// Returns the path of child indexes leading to it
+    pub fn locate(&self, key: &TrieKey<K>) -> Option<Vec<usize>> {
+        let mut path = Vec::new();
+        let mut node = self;
+        let mut offset = 0;
+        let mut rest = key.key.symbols();
+        loop {
+            let ChildPosition::Shared { index, lcp, full_match, .. } = node.find_child(key, offset, &rest) else {
+                return None;
+            };
+            path.push(index);
+            node = node.children.get(index).unwrap();
+            if full_match {
+                return Some(path);
+            }
+            if lcp < node.node_key.key.key_len() {
+                return None;
             }
+            offset += lcp;
+            advance(&mut rest, lcp);
         }
+    }
 
-        if full_match {
-            // Node already exists and is a full match
-            // Alternatives:
-            // 1. Colliding node is leaf -> Just replace
-            // 2. Colliding node is aux -> TBD
-            self.children.get_mut(prev_insert_index).unwrap().value = value.clone();
+    /// Gets the node at the end of a path returned by [RFRNode::locate]
+    #[inline]
+    pub fn node_at(&self, path: &[usize]) -> &RFRNode<K, V> {
+        path.iter().fold(self, |node, index| node.children.get(*index).unwrap())
+    }
+
+    /// Gets the mutable node at the end of a path returned by [RFRNode::locate]
+    #[inline]
+    pub fn node_at_mut(&mut self, path: &[usize]) -> &mut RFRNode<K, V> {
+        path.iter().fold(self, |node, index| node.children.get_mut(*index).unwrap())
+    }
+
+    /// Removes the value stored under the exact `key`, returning it.
+    /// Nodes left without value are dropped or merged with their single child.
+    #[inline]
+    pub fn remove(&mut self, key: &TrieKey<K>) -> Option<V> {
+        self.remove_from(key, 0, key.key.symbols())
+    }
+
+    /// Removes the value stored under the postfix of `key` starting at `offset` (`rest` being its symbols)
+    fn remove_from<I>(&mut self, key: &TrieKey<K>, offset: usize, mut rest: I) -> Option<V>
+        where I: Iterator<Item = K::Symbol> + Clone
+    {
+        let ChildPosition::Shared { index, lcp, full_match, .. } = self.find_child(key, offset, &rest) else {
+            return None;
+        };
+        let child = self.children.get_mut(index).unwrap();
+        let removed = if full_match {
+            child.value.take()
+        }
+        else if lcp == child.node_key.key.key_len() {
+            advance(&mut rest, lcp);
+            child.remove_from(key, offset + lcp, rest)
         }
         else {
-            if lcp > 0 { // Partial collision
-                // Child is leaf: Split
-                let prev_node = self.children.get_mut(prev_insert_index).unwrap();
-
-                let common_prefix = prev_node.node_key.key.new_from_key_prefix(lcp);
-                let prev_node_postfix = prev_node.node_key.key.new_from_postfix(lcp);
-
-                let new_node_postfix = key.key.new_from_postfix(lcp);
-                let new_node_postfix_kl = new_node_postfix.key_len();
-                let prev_node_postfix_kl = prev_node_postfix.key_len();
-                if prev_node_postfix_kl != 0 {
-                    let mut prev_node = self.children.remove(prev_insert_index);
-                    prev_node.strip_prefix(lcp);
-                    let mut aux: Box<RFRNode<K, V>> = if new_node_postfix_kl != 0 || value.is_none() {
-                        Box::new(RFRNode::new_aux(
-                            TrieKey::new(common_prefix)
-                        ))
-                    }
-                    else {
-                        Box::new(RFRNode::new_leaf_with_prefix(
-                            TrieKey::new(common_prefix),
-                            value.as_ref().unwrap().clone()
-                            ))
-                    };
-                    aux.insert(TrieKey::new(prev_node_postfix), prev_node.value);
-                    for _idx in 0..prev_node.children.len() {
-                        let prev_node_child = prev_node.children.remove(0);
-                        aux.children.get_mut(0).unwrap().children.push(prev_node_child);
-                    }
-                    if new_node_postfix_kl != 0 {
-                        aux.insert(TrieKey::new(new_node_postfix), value.clone());
-                    }
-                    self.children.insert(prev_insert_index, aux);
+            None
+        };
+        if removed.is_some() {
+            self.compact_child(index);
+        }
+        removed
+    }
+
+    /// Re-compacts the child at `index` once it does not hold a value anymore
+    fn compact_child(&mut self, index: usize) {
+        let child = self.children.remove(index);
+        if let Some(child) = Self::compacted(child) {
+            let index = self.sorted_index(&child.node_key.key);
+            self.children.insert(index, child);
+        }
+    }
+
+    /// The `node` once it does not hold a value anymore: dropped when dangling, merged with its single
+    /// child otherwise (which may move it among its siblings)
+    fn compacted(mut node: Box<Self>) -> Option<Box<Self>> {
+        if node.value.is_some() {
+            return Some(node);
+        }
+        match node.children.len() {
+            // Dangling aux node
+            0 => None,
+            1 => {
+                // Aux node with a single child: merge both
+                let child = node.children.remove(0);
+                node.node_key = TrieKey::new(node.node_key.key.new_from_concat(&child.node_key.key));
+                node.value = child.value;
+                node.children = child.children;
+                Some(node)
+            }
+            _ => Some(node),
+        }
+    }
+
+    #[inline]
+    pub fn lookup<M, I>(&self, match_key: I) -> TrieIterator<'_, K, V, M, I>
+        where M: PushdownStateMachine<K::Symbol> + Clone, I: Iterator<Item = K::Symbol> + Clone
+    {
+        TrieIterator::new(self, match_key)
+    }
+
+    #[inline]
+    pub fn get<M, I>(&self, match_key: I) -> Option<V>
+        where M: PushdownStateMachine<K::Symbol> + Clone, I: Iterator<Item = K::Symbol> + Clone
+    {
+        self.lookup::<M, I>(match_key).next_match().map(|(_, value, _)| value.clone())
+    }
+
+    #[inline]
+    pub fn get_with_key<M, I>(&self, match_key: I) -> Option<(K, &V)>
+        where M: PushdownStateMachine<K::Symbol> + Clone, I: Iterator<Item = K::Symbol> + Clone
+    {
+        self.lookup::<M, I>(match_key).next()
+    }
+
+    pub fn get_merge<M, I>(&self, match_key: I) -> Option<V>
+        where M: PushdownStateMachine<K::Symbol> + Clone, I: Iterator<Item = K::Symbol> + Clone, V: ValueMerge + Debug
+    {
+        let mut matches = self.lookup::<M, I>(match_key);
+        let mut acc_value = matches.next_match().map(|(_, value, _)| value.clone())?;
+        while let Some((_, value, _)) = matches.next_match() {
+            acc_value.merge_mut(value);
+        }
+        Some(acc_value)
+    }
+
+    pub fn get_merge_with_keys<M, I>(&self, match_key: I) -> Option<(V, Vec<K>)>
+        where M: PushdownStateMachine<K::Symbol> + Clone, I: Iterator<Item = K::Symbol> + Clone, V: ValueMerge + Debug
+    {
+        let mut acc_value: Option<V> = None;
+        let mut keys = Vec::new();
+        for (matched_key, value) in self.lookup::<M, I>(match_key) {
+            match acc_value.as_mut() {
+                None => {
+                    acc_value.replace(value.clone());
                 }
-                else {
-                    prev_node.insert(TrieKey::new(new_node_postfix), value.clone());
+                Some(acc) => {
+                    acc.merge_mut(value);
                 }
             }
-            else {
-                // No collition -> Just insert as a leaf
-                if value.is_some() {
-                    self.children.insert(insert_index, Box::new(RFRNode::new_leaf_with_prefix(
-                        key,
-                        value.as_ref().unwrap().clone()
-                    )));
-                    return None;
+            keys.push(matched_key);
+        }
+        acc_value.map(|value| (value, keys))
+    }
+
+    /// Stored keys the `match_key` symbols start with, compared literally (no matcher involved),
+    /// shortest first. Only the children prefixing the rest of the match key are walked
+    pub fn prefixes_of<I: Iterator<Item = K::Symbol> + Clone>(&self, match_key: I) -> Vec<(K, &V)> {
+        let mut prefixes = Vec::new();
+        let mut stack = vec![(self, K::empty(), 0_usize, match_key)];
+        while let Some((node, key, key_len, rest)) = stack.pop() {
+            if let Some(value) = &node.value {
+                prefixes.push((key_len, key.clone(), value));
+            }
+            for child in node.children.iter().rev() {
+                let mut child_rest = rest.clone();
+                if child.node_key.key.symbols().all(|symbol| child_rest.next() == Some(symbol)) {
+                    let child_key_len = key_len + child.node_key.key.key_len();
+                    stack.push((child.as_ref(), key.new_from_concat(&child.node_key.key), child_key_len, child_rest));
                 }
-                else {
-                    self.children.insert(insert_index, Box::new(RFRNode::new_aux(
-                        key,
-                    )));
+            }
+        }
+        // Pattern keys may get split apart at tokens, so several branches can prefix the match key
+        prefixes.sort_by_key(|(key_len, _, _)| *key_len);
+        prefixes.into_iter().map(|(_, key, value)| (key, value)).collect()
+    }
+
+    /// The longest stored key the `match_key` symbols start with, compared literally (no matcher
+    /// involved). Walks the children prefixing the rest of the match key once, keeping the deepest value
+    pub fn longest_prefix<I: Iterator<Item = K::Symbol> + Clone>(&self, match_key: I) -> Option<(K, &V)> {
+        let mut longest: Option<(usize, K, &V)> = None;
+        let mut stack = vec![(self, K::empty(), 0_usize, match_key)];
+        while let Some((node, key, key_len, rest)) = stack.pop() {
+            if let Some(value) = &node.value {
+                if longest.as_ref().map_or(true, |(longest_len, _, _)| key_len > *longest_len) {
+                    longest = Some((key_len, key.clone(), value));
+                }
+            }
+            for child in node.children.iter() {
+                let mut child_rest = rest.clone();
+                if child.node_key.key.symbols().all(|symbol| child_rest.next() == Some(symbol)) {
+                    let child_key_len = key_len + child.node_key.key.key_len();
+                    stack.push((child.as_ref(), key.new_from_concat(&child.node_key.key), child_key_len, child_rest));
                 }
             }
         }
-        return value;
+        longest.map(|(_, key, value)| (key, value))
     }
 
-    #[inline]
-    pub fn lookup<M: PushdownStateMachine + Clone>(&self, match_key: &K) -> TrieIterator<'_, K, V, M> {
-        TrieIterator::new(self, match_key)
+    /// Stored keys accepted by the `matcher` (already stepped into a pattern) when fed as match keys,
+    /// in order. Children are walked only while the matcher accepts more symbols
+    pub fn matched_by<M: PushdownStateMachine<K::Symbol> + Clone>(&self, matcher: M) -> Vec<(K, &V)> {
+        let mut matched = Vec::new();
+        let mut stack = vec![(self, K::empty(), matcher)];
+        while let Some((node, key, matcher)) = stack.pop() {
+            if let Some(value) = &node.value {
+                let mut end_matcher = matcher.clone();
+                end_matcher.feed(Event::EndOfStream);
+                if end_matcher.state() == State::Accepted {
+                    matched.push((key.clone(), value));
+                }
+            }
+            for child in node.children.iter().rev() {
+                let mut child_matcher = matcher.clone();
+                let fed = child.node_key.key.symbols().all(|symbol| {
+                    let accepts_more = child_matcher.accepts_more();
+                    if accepts_more {
+                        child_matcher.feed(Event::CharIn(symbol));
+                    }
+                    accepts_more
+                });
+                if fed {
+                    stack.push((child.as_ref(), key.new_from_concat(&child.node_key.key), child_matcher));
+                }
+            }
+        }
+        // Siblings extending each other's key (e.g. `*` and `**`) are not walked in key order
+        matched.sort_by(|(first, _), (second, _)| cmp_keys(first, second));
+        matched
     }
 
-    #[inline]
-    pub fn get<M: PushdownStateMachine + Clone>(&self, key: &K) -> Option<V> {
-        for value in self.lookup::<M>(key) {
-            return Some(value);
+    /// Number of values held by this node and its descendants
+    pub fn count(&self) -> usize {
+        usize::from(self.value.is_some()) + self.children.iter().map(|child| child.count()).sum::<usize>()
+    }
+
+    /// Compares the node key with a key `prefix`, given as symbols
+    fn prefix_position<I: Iterator<Item = K::Symbol> + Clone>(&self, mut prefix: I) -> PrefixPosition<I> {
+        for symbol in self.node_key.key.symbols() {
+            match prefix.next() {
+                None => return PrefixPosition::Within,
+                Some(prefix_symbol) if prefix_symbol == symbol => {}
+                Some(_) => return PrefixPosition::Diverging,
+            }
+        }
+        if prefix.clone().next().is_none() {
+            PrefixPosition::Within
+        }
+        else {
+            PrefixPosition::Beyond(prefix)
         }
-        None
     }
 
-    pub fn get_merge<M: PushdownStateMachine + Clone>(&self, key: &K) -> Option<V>
-        where V: ValueMerge + Debug
+    /// Roots of the subtrees holding the stored keys starting with the `prefix` symbols, in key order,
+    /// each as a one node slice along with the full key of its parent. A root key may go beyond the
+    /// prefix, when the prefix ends in the middle of a node key
+    pub fn prefix_roots<I: Iterator<Item = K::Symbol> + Clone>(&self, prefix: I) -> Vec<Subtrees<'_, K, V>> {
+        let mut roots = Vec::new();
+        self.collect_prefix_roots(&K::empty(), prefix, &mut roots);
+        roots
+    }
+
+    fn collect_prefix_roots<'a, I>(&'a self, key: &K, prefix: I, roots: &mut Vec<Subtrees<'a, K, V>>)
+        where I: Iterator<Item = K::Symbol> + Clone
     {
-        let mut acc_value = None;
-        for value in self.lookup::<M>(key) {
-            if acc_value.is_none() {
-                acc_value.replace(value);
+        for (index, child) in self.children.iter().enumerate() {
+            match child.prefix_position(prefix.clone()) {
+                PrefixPosition::Within => roots.push((key.clone(), &self.children[index..=index])),
+                PrefixPosition::Beyond(rest) => {
+                    child.collect_prefix_roots(&key.new_from_concat(&child.node_key.key), rest, roots)
+                }
+                PrefixPosition::Diverging => {}
             }
-            else {
-                acc_value.as_mut().unwrap().merge_mut(&value);
+        }
+    }
+
+    /// Detaches the subtrees holding the stored keys starting with the `prefix` symbols, returning
+    /// their roots in key order, each along with the full key of its parent (this node key being `key`).
+    /// Nodes left without value are dropped or merged with their single child
+    pub fn remove_prefix<I: Iterator<Item = K::Symbol> + Clone>(&mut self, key: &K, prefix: I) -> Vec<(K, RFRNode<K, V>)> {
+        let mut removed = Vec::new();
+        let mut compacted = false;
+        for mut child in std::mem::take(&mut self.children) {
+            match child.prefix_position(prefix.clone()) {
+                PrefixPosition::Within => removed.push((key.clone(), *child)),
+                PrefixPosition::Beyond(rest) => {
+                    let child_key = key.new_from_concat(&child.node_key.key);
+                    let detached = child.remove_prefix(&child_key, rest);
+                    if detached.is_empty() {
+                        self.children.push(child);
+                    }
+                    else {
+                        removed.extend(detached);
+                        self.children.extend(Self::compacted(child));
+                        compacted = true;
+                    }
+                }
+                PrefixPosition::Diverging => self.children.push(child),
+            }
+        }
+        if compacted {
+            // Merged children may have moved
+            self.children.sort_by(|child0, child1| child0.node_key.key.symbols().cmp(child1.node_key.key.symbols()));
+        }
+        removed
+    }
+
+    /// Inserts a `subtree` whose node key is relative to `parent_key`, along with all its descendants
+    pub fn insert_subtree(&mut self, parent_key: &K, subtree: RFRNode<K, V>) {
+        let key = parent_key.new_from_concat(&subtree.node_key.key);
+        let trie_key = TrieKey::new(key.clone());
+        if subtree.value.is_some() || self.locate(&trie_key).is_none() {
+            self.insert(trie_key.clone(), subtree.value);
+        }
+        let path = self.locate(&trie_key).unwrap();
+        let node = self.node_at_mut(&path);
+        if node.children.is_empty() {
+            node.children = subtree.children;
+        }
+        else {
+            // Another subtree got there first
+            for child in subtree.children {
+                self.insert_subtree(&key, *child);
             }
         }
-        acc_value
     }
 
     #[inline]
@@ -187,7 +473,7 @@ impl<K, V> RFRNode<K, V> where K: KeyPrefix + Clone, V: Clone {
         self.children.iter()
     }
 
-    pub fn foreach<F>(&self, level: usize, f: &F) -> ()
+    pub fn foreach<F>(&self, level: usize, f: &F)
         where F: Fn((usize, &K, &Option<V>))
     {
         for item in self.iter() {
@@ -199,3 +485,31 @@ impl<K, V> RFRNode<K, V> where K: KeyPrefix + Clone, V: Clone {
 
 
 
+
+/// Sibling subtrees, along with the full key of their parent
+pub type Subtrees<'a, K, V> = (K, &'a [Box<RFRNode<K, V>>]);
+
+/// Where a key stands among the children of a node
+enum ChildPosition {
+    /// The child at `index` shares `lcp` symbols with the key, down to a prefix boundary of both
+    Shared { index: usize, lcp: usize, full_match: bool },
+    /// No child shares a boundary with the key, which would be inserted at that index
+    Vacant(usize),
+}
+
+/// Where a key prefix stands with respect to a node key
+enum PrefixPosition<I> {
+    /// The prefix ends within the node key, or right at its end
+    Within,
+    /// The node key is a prefix of the prefix, followed by the `I` symbols
+    Beyond(I),
+    Diverging,
+}
+
+impl<K, V> PartialEq for RFRNode<K, V> where K: KeyPrefix + Clone + PartialEq, V: Clone + PartialEq {
+    fn eq(&self, other: &Self) -> bool {
+        self.node_key.key == other.node_key.key
+            && self.value == other.value
+            && self.children == other.children
+    }
+}
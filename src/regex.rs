@@ -0,0 +1,308 @@
+//! Keys holding a restricted, implicitly anchored regex (optional)
+//!
+//! Supported syntax: `.` (any char), `[a-z]` / `[^0-9]` (classes), `(ab|cd)` (groups), the greedy
+//! `*`, `+`, `?`, `{m}`, `{m,}` and `{m,n}` quantifiers, and `\` escaping the next char.
+//! Malformed constructs, and quantifiers expanding beyond [MAX_REPEAT] copies, are kept as literals.
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+use serde::{Serialize, Deserialize};
+use crate::fold::Folding;
+use crate::glob::GlobMatcher;
+use crate::key::{advance, text_key_prefix, KeyPrefix, KeyQuery};
+use crate::matcher::{CharClass, MatchType, RegexNode, SequenceBuilder, StateSequence};
+
+/// Regex constructs compile into glob tokens, so the glob machine evaluates them
+pub type RegexMatcher = GlobMatcher;
+
+/// Most copies of an atom a counted quantifier may expand to, nested quantifiers multiplying.
+/// Quantifiers going beyond are kept as literals
+pub const MAX_REPEAT: usize = 1000;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegexKey {
+    pub pattern: String,
+}
+
+impl RegexKey {
+    pub fn new(pattern: &str) -> Self {
+        Self {
+            pattern: pattern.to_string()
+        }
+    }
+}
+
+///////////////////////////
+
+impl Display for RegexKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("{}", self.pattern))
+    }
+}
+
+impl KeyPrefix for RegexKey {
+
+    text_key_prefix!(pattern);
+
+    /// Only literal chars get folded
+    #[inline]
+    fn folded(&self, folding: &Folding) -> Self {
+        Self {
+            pattern: folding.fold_pattern(&self.pattern, Self::is_syntax, Some('\\'))
+        }
+    }
+
+    /// Match keys have no syntax: all their chars get folded
+    #[inline]
+    fn folded_query(&self, folding: &Folding) -> Self {
+        Self {
+            pattern: folding.fold(&self.pattern)
+        }
+    }
+
+    /// Quantified atoms, classes and groups are never split across nodes
+    #[inline]
+    fn prefix_boundary(&self, index: usize) -> usize {
+        let mut chars = self.pattern.chars();
+        let mut boundary = 0;
+        while boundary < index && chars.clone().next().is_some() {
+            let (_, len, _) = Self::parse_quantified(chars.clone(), false);
+            if boundary + len > index {
+                break;
+            }
+            boundary += len;
+            advance(&mut chars, len);
+        }
+        boundary
+    }
+
+    /// Unquantified chars make the literal tails, and every other atom a leading construct
+    #[inline]
+    fn compiled(&self) -> Vec<Arc<StateSequence>> {
+        let mut builder = SequenceBuilder::new();
+        let mut chars = self.pattern.chars();
+        while chars.clone().next().is_some() {
+            let (node, len, _) = Self::parse_quantified(chars.clone(), true);
+            advance(&mut chars, len);
+            match node.unwrap() {
+                RegexNode::Char(ch) => builder.literal(ch),
+                RegexNode::Any => builder.construct(MatchType::AnyChar),
+                RegexNode::Class(class) => builder.construct(MatchType::Class(class)),
+                node => builder.construct(MatchType::Regex(node)),
+            }
+        }
+        builder.build()
+    }
+}
+
+impl KeyQuery<RegexKey> for str {
+
+    #[inline]
+    fn query_symbols(&self) -> impl Iterator<Item = char> + Clone + '_ {
+        self.chars()
+    }
+
+    #[inline]
+    fn to_key(&self) -> RegexKey {
+        RegexKey::new(self)
+    }
+}
+
+impl RegexKey {
+
+    /// Parses the atom starting `chars` (which must not be empty) along with its quantifier if any,
+    /// returning its length and the number of atom copies it expands to. Nodes are only built when
+    /// told to, so boundaries get found without allocating
+    fn parse_quantified<I>(chars: I, build: bool) -> (Option<RegexNode>, usize, usize)
+        where I: Iterator<Item = char> + Clone
+    {
+        let (atom, atom_len, atom_size) = Self::parse_atom(chars.clone(), build);
+        let mut rest = chars;
+        advance(&mut rest, atom_len);
+        let quantifier = Self::parse_quantifier(rest).and_then(|((min, max), quantifier_len)| {
+            let size = atom_size.checked_mul(max.unwrap_or(min + 1).max(1))?;
+            (size <= MAX_REPEAT).then_some(((min, max), quantifier_len, size))
+        });
+        match quantifier {
+            None => (atom, atom_len, atom_size),
+            Some(((min, max), quantifier_len, size)) => {
+                (atom.map(|node| RegexNode::Repeat { node: Box::new(node), min, max }), atom_len + quantifier_len, size)
+            }
+        }
+    }
+
+    /// Whether `ch` may be part of the regex syntax, depending on where it stands
+    #[inline]
+    fn is_syntax(ch: char) -> bool {
+        matches!(ch, '.' | '[' | ']' | '^' | '-' | '(' | '|' | ')' | '*' | '+' | '?' | '{' | ',' | '}' | '0'..='9')
+    }
+
+    /// Parses the atom starting `chars` (which must not be empty), along with its length and size.
+    /// Anything but a well formed construct is a literal char
+    fn parse_atom<I>(mut chars: I, build: bool) -> (Option<RegexNode>, usize, usize)
+        where I: Iterator<Item = char> + Clone
+    {
+        match chars.next().unwrap() {
+            '\\' => match chars.next() {
+                Some(ch) => (build.then_some(RegexNode::Char(ch)), 2, 1),
+                None => (build.then_some(RegexNode::Char('\\')), 1, 1),
+            },
+            '.' => (build.then_some(RegexNode::Any), 1, 1),
+            '[' => {
+                let mut ranges = Vec::new();
+                match CharClass::scan(chars, |low, high| if build { ranges.push((low, high)) }) {
+                    Some((negated, len)) => (build.then_some(RegexNode::Class(CharClass { negated, ranges })), len + 1, 1),
+                    None => (build.then_some(RegexNode::Char('[')), 1, 1),
+                }
+            }
+            '(' => match Self::parse_group(chars, build) {
+                Some((branches, len, size)) => (build.then_some(RegexNode::Group(branches)), len + 1, size.max(1)),
+                None => (build.then_some(RegexNode::Char('(')), 1, 1),
+            },
+            ch => (build.then_some(RegexNode::Char(ch)), 1, 1),
+        }
+    }
+
+    /// Parses a group following `(`, returning its branches (left empty unless built) along with
+    /// the consumed chars (`)` included) and the atom copies of all its branches
+    fn parse_group<I>(mut chars: I, build: bool) -> Option<(Vec<Vec<RegexNode>>, usize, usize)>
+        where I: Iterator<Item = char> + Clone
+    {
+        let mut branches = Vec::new();
+        let mut branch = Vec::new();
+        let mut position = 0;
+        let mut size = 0_usize;
+        loop {
+            match chars.clone().next()? {
+                ')' => {
+                    if build {
+                        branches.push(branch);
+                    }
+                    return Some((branches, position + 1, size));
+                }
+                '|' => {
+                    if build {
+                        branches.push(std::mem::take(&mut branch));
+                    }
+                    chars.next();
+                    position += 1;
+                }
+                _ => {
+                    let (node, len, node_size) = Self::parse_quantified(chars.clone(), build);
+                    branch.extend(node);
+                    advance(&mut chars, len);
+                    position += len;
+                    size = size.saturating_add(node_size);
+                }
+            }
+        }
+    }
+
+    /// Parses the quantifier starting `chars`, if any, returning its bounds along with its length
+    fn parse_quantifier(mut chars: impl Iterator<Item = char>) -> Option<((usize, Option<usize>), usize)> {
+        match chars.next()? {
+            '*' => Some(((0, None), 1)),
+            '+' => Some(((1, None), 1)),
+            '?' => Some(((0, Some(1)), 1)),
+            '{' => {
+                // Bounds before and after the comma, if any
+                let mut bounds: [Option<usize>; 2] = [None, None];
+                let mut comma = false;
+                let mut len = 1;
+                loop {
+                    len += 1;
+                    match chars.next()? {
+                        '}' => break,
+                        ',' if !comma => comma = true,
+                        ch @ '0'..='9' => {
+                            let bound = &mut bounds[usize::from(comma)];
+                            let digit = ch.to_digit(10).unwrap() as usize;
+                            *bound = Some(bound.unwrap_or(0).checked_mul(10)?.checked_add(digit)?);
+                        }
+                        _ => return None,
+                    }
+                }
+                let min = bounds[0]?;
+                let max = if comma { bounds[1] } else { Some(min) };
+                if max.is_some_and(|max| max < min) {
+                    return None;
+                }
+                Some(((min, max), len))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::trie::Trie;
+    use crate::regex::*;
+
+    #[test]
+    fn regex_key_test() {
+        let compiled = RegexKey::new("/v[0-9]+/items").compiled();
+        assert_eq!(2, compiled.len());
+        assert_eq!(vec!['/', 'v'], compiled[0].sequence);
+        assert_eq!(MatchType::Regex(RegexNode::Repeat {
+            node: Box::new(RegexNode::Class(CharClass { negated: false, ranges: vec![('0', '9')] })),
+            min: 1,
+            max: None,
+        }), compiled[1].match_type);
+
+        // Malformed constructs are literals
+        let compiled = RegexKey::new("(a[b{2,1}").compiled();
+        assert_eq!(1, compiled.len());
+        assert_eq!(MatchType::Literal, compiled[0].match_type);
+
+        let mut trie = Trie::new();
+        trie.insert(RegexKey::new("/v[0-9]+/items"), 1);
+        trie.insert(RegexKey::new("/v1/items"), 2);
+        trie.insert(RegexKey::new("/v[0-9]+/orders?"), 3);
+        trie.insert(RegexKey::new("/(users|groups)/[a-z]+"), 4);
+        trie.insert(RegexKey::new("/files/(ab|c)+\\.txt"), 5);
+        trie.insert(RegexKey::new("/x{2,3}"), 6);
+        trie.insert(RegexKey::new("/x{4,}"), 7);
+        trie.insert(RegexKey::new("/.?"), 8);
+
+        let get = |trie: &Trie<RegexKey, i32>, path: &str| {
+            let mut values: Vec<i32> = trie.matches::<RegexMatcher>(&RegexKey::new(path)).map(|(_, value)| *value).collect();
+            values.sort_unstable();
+            values
+        };
+        assert_eq!(vec![1, 2], get(&trie, "/v1/items"));
+        assert_eq!(vec![1], get(&trie, "/v42/items"));
+        assert_eq!(Vec::<i32>::new(), get(&trie, "/v/items"));
+        assert_eq!(vec![3], get(&trie, "/v2/order"));
+        assert_eq!(vec![3], get(&trie, "/v2/orders"));
+        assert_eq!(vec![4], get(&trie, "/groups/admin"));
+        assert_eq!(Vec::<i32>::new(), get(&trie, "/roles/admin"));
+        assert_eq!(vec![5], get(&trie, "/files/abcab.txt"));
+        assert_eq!(Vec::<i32>::new(), get(&trie, "/files/.txt"));
+        assert_eq!(Vec::<i32>::new(), get(&trie, "/files/abbtxt"));
+        assert_eq!(vec![6], get(&trie, "/xxx"));
+        assert_eq!(vec![7], get(&trie, "/xxxxx"));
+        assert_eq!(vec![8], get(&trie, "/x"));
+        assert_eq!(vec![8], get(&trie, "/"));
+
+        // Nodes hold whole atoms
+        for (key, value) in &trie {
+            assert_eq!(Some(value), trie.get_exact(&key));
+        }
+
+        // Repetitions expanding beyond the cap are literals, nested quantifiers multiplying
+        let repeats = |pattern: &str| RegexKey::new(pattern).compiled().iter()
+            .any(|token| matches!(token.match_type, MatchType::Regex(RegexNode::Repeat { .. })));
+        assert!(repeats(&format!("a{{{}}}", MAX_REPEAT)));
+        assert!(!repeats(&format!("a{{{}}}", MAX_REPEAT + 1)));
+        assert!(!repeats("a{1000000}"));
+        assert!(!repeats("a{1,99999999999999999999}"));
+        let compiled = RegexKey::new("(a{100}){100}").compiled();
+        assert_eq!(1, compiled.len());
+        assert!(matches!(compiled[0].match_type, MatchType::Regex(RegexNode::Group(_))));
+        assert_eq!(vec!['{', '1', '0', '0', '}'], compiled[0].sequence);
+        let mut trie = Trie::new();
+        trie.insert(RegexKey::new("x{1000000}"), 9);
+        assert_eq!(vec![9], get(&trie, "x{1000000}"));
+        assert_eq!(Vec::<i32>::new(), get(&trie, "xx"));
+    }
+}
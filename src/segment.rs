@@ -0,0 +1,321 @@
+//! Keys made of whole segments (path components, DNS labels, topic levels...) rather than chars
+//!
+//! Nodes are split at segment boundaries only, so `/path/to` and `/pathology` share no node, and
+//! the [SegmentMatcher] wildcards match whole segments.
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
+use crate::trie::Trie;
+use crate::key::{KeyPrefix, KeyQuery};
+use crate::matcher::{Event, PushdownStateMachine, State, StateSequence};
+
+/// A key segment, the symbol of [SegmentKey]s
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Segment<T> {
+    Literal(T),
+    /// Any single segment
+    Any,
+    /// Any number of segments, none included
+    AnyLevels,
+}
+
+/// A sequence of segments. Segments get cloned when keys are compared, so only cheaply cloned
+/// segment types (e.g. `Arc<str>` or interned ids) keep comparisons allocation free: `String`
+/// segments allocate on every comparison
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SegmentKey<T> {
+    pub segments: Vec<Segment<T>>,
+}
+
+pub type SegmentTrie<T, V> = Trie<SegmentKey<T>, V>;
+
+impl<T> SegmentKey<T> {
+    pub fn new(segments: Vec<Segment<T>>) -> Self {
+        Self {
+            segments
+        }
+    }
+
+    /// A key of literal segments only
+    pub fn literal(segments: impl IntoIterator<Item = T>) -> Self {
+        Self {
+            segments: segments.into_iter().map(Segment::Literal).collect()
+        }
+    }
+}
+
+impl SegmentKey<Arc<str>> {
+    /// Splits `text` at every `separator`, `*` segments matching any single segment and `**` any number of them.
+    /// Literal segments are shared, so comparing keys clones no text
+    pub fn split(text: &str, separator: char) -> Self {
+        Self {
+            segments: text.split(separator)
+                .map(|segment| match segment {
+                    "*" => Segment::Any,
+                    "**" => Segment::AnyLevels,
+                    literal => Segment::Literal(Arc::from(literal)),
+                })
+                .collect()
+        }
+    }
+}
+
+///////////////////////////
+
+impl<T: Display> Display for SegmentKey<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (index, segment) in self.segments.iter().enumerate() {
+            if index > 0 {
+                f.write_str("/")?;
+            }
+            match segment {
+                Segment::Literal(literal) => literal.fmt(f)?,
+                Segment::Any => f.write_str("*")?,
+                Segment::AnyLevels => f.write_str("**")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T: Clone + Ord + std::fmt::Debug + Serialize + DeserializeOwned> KeyPrefix for SegmentKey<T> {
+
+    type Symbol = Segment<T>;
+
+    #[inline]
+    fn symbols(&self) -> impl Iterator<Item = Segment<T>> + Clone + '_ {
+        self.segments.iter().cloned()
+    }
+
+    #[inline]
+    fn key_len(&self) -> usize {
+        self.segments.len()
+    }
+
+    #[inline]
+    fn empty() -> Self {
+        Self {
+            segments: Vec::new(),
+        }
+    }
+
+    #[inline]
+    fn new_from_key_prefix(&self, index: usize) -> Self {
+        Self {
+            segments: self.segments[..index].to_vec()
+        }
+    }
+
+    #[inline]
+    fn new_from_postfix(&self, index: usize) -> Self {
+        Self {
+            segments: self.segments[index..].to_vec()
+        }
+    }
+
+    #[inline]
+    fn new_from_concat(&self, postfix: &Self) -> Self {
+        Self {
+            segments: [self.segments.as_slice(), postfix.segments.as_slice()].concat()
+        }
+    }
+}
+
+impl<T: Clone + Ord + std::fmt::Debug + Serialize + DeserializeOwned> KeyQuery<SegmentKey<T>> for [Segment<T>] {
+
+    #[inline]
+    fn query_symbols(&self) -> impl Iterator<Item = Segment<T>> + Clone + '_ {
+        self.iter().cloned()
+    }
+
+    #[inline]
+    fn to_key(&self) -> SegmentKey<T> {
+        SegmentKey::new(self.to_vec())
+    }
+}
+
+/////////////////////////////
+
+#[derive(Debug, Clone)]
+struct Reach<T> {
+    /// Match key segments fed so far
+    query: Vec<Segment<T>>,
+    end_of_stream: bool,
+    /// Stored key segments stepped in before the whole match key was known
+    pending: Vec<Segment<T>>,
+    /// Whether the stored key stepped in so far matches each match key prefix (by length),
+    /// once the whole match key is known
+    row: Vec<bool>,
+}
+
+impl<T: Eq> Reach<T> {
+
+    #[inline]
+    fn new() -> Self {
+        Self {
+            query: Vec::new(),
+            end_of_stream: false,
+            pending: Vec::new(),
+            row: Vec::new(),
+        }
+    }
+
+    /// Computes the match key prefixes matched by the stored key extended with `segment`
+    fn push_row(&mut self, segment: &Segment<T>) {
+        self.row = match segment {
+            Segment::Literal(literal) => (0..=self.query.len())
+                .map(|length| length > 0 && self.row[length - 1]
+                    && matches!(&self.query[length - 1], Segment::Literal(query) if query == literal))
+                .collect(),
+            Segment::Any => (0..=self.query.len())
+                .map(|length| length > 0 && self.row[length - 1])
+                .collect(),
+            Segment::AnyLevels => self.row.iter()
+                .scan(false, |reached, matched| {
+                    *reached |= *matched;
+                    Some(*reached)
+                })
+                .collect(),
+        };
+    }
+}
+
+/// Accepts the stored [SegmentKey]s matching the match key segments, [Segment::Any] matching any
+/// single segment and [Segment::AnyLevels] any number of them. Match key segments are literals
+#[derive(Debug, Clone)]
+pub struct SegmentMatcher<T> {
+    stack: Vec<Reach<T>>,
+}
+
+impl<T: Clone + Eq> PushdownStateMachine<Segment<T>> for SegmentMatcher<T> {
+    fn new() -> Self {
+        Self {
+            stack: Vec::new(),
+        }
+    }
+
+    #[inline]
+    fn step_in(&mut self, sequence: &Vec<Arc<StateSequence<Segment<T>>>>) {
+        let mut reach = self.stack.last().cloned().unwrap_or_else(Reach::new);
+        for segment in sequence.iter().flat_map(|token| token.sequence.iter()) {
+            if reach.end_of_stream {
+                reach.push_row(segment);
+            }
+            else {
+                reach.pending.push(segment.clone());
+            }
+        }
+        self.stack.push(reach);
+    }
+
+    #[inline]
+    fn step_out(&mut self) {
+        let _k = self.stack.pop();
+    }
+
+    /// The whole match key is needed, as [Segment::AnyLevels] may span any of it
+    #[inline]
+    fn accepts_more(&self) -> bool {
+        match self.stack.last() {
+            Some(reach) => !reach.end_of_stream,
+            None => false
+        }
+    }
+
+    #[inline]
+    fn feed(&mut self, ev: Event<Segment<T>>) {
+        if let Some(reach) = self.stack.last_mut() {
+            match ev {
+                Event::CharIn(segment) => {
+                    reach.query.push(segment);
+                }
+                Event::EndOfStream if !reach.end_of_stream => {
+                    reach.end_of_stream = true;
+                    reach.row = (0..=reach.query.len()).map(|length| length == 0).collect();
+                    for segment in std::mem::take(&mut reach.pending) {
+                        reach.push_row(&segment);
+                    }
+                }
+                Event::EndOfStream => {}
+            }
+        }
+    }
+
+    #[inline]
+    fn state(&self) -> State {
+        match self.stack.last() {
+            Some(reach) if !reach.end_of_stream => State::Expecting,
+            Some(reach) => {
+                if reach.row.last().copied().unwrap_or_default() {
+                    State::Accepted
+                }
+                else if reach.row.contains(&true) {
+                    // Longer stored keys may match the rest of the match key
+                    State::Expecting
+                }
+                else {
+                    State::Rejected
+                }
+            }
+            None => State::Failure(String::from("Machine not initiallized"))
+        }
+    }
+
+    #[inline]
+    fn is_sink(&self) -> bool {
+        !matches!(self.state(), State::Accepting | State::Expecting)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::key::TrieKey;
+    use crate::literal::PrefixMatcher;
+    use crate::segment::*;
+
+    #[test]
+    fn segment_key_test() {
+        let path = |text: &str| SegmentKey::split(text, '/');
+        // Only the leading empty segment is shared
+        assert_eq!(1, TrieKey::new(path("/path/to")).lcp(&TrieKey::new(path("/pathology"))).0);
+        assert_eq!("/a/*/**", path("/a/*/**").to_string());
+
+        let mut trie = SegmentTrie::new();
+        trie.insert(path("/path/to"), 1);
+        trie.insert(path("/pathology"), 2);
+        trie.insert(path("/path/*/c"), 3);
+        trie.insert(path("/path/**"), 4);
+        trie.insert(path("/**/c"), 5);
+        let get = |trie: &SegmentTrie<Arc<str>, i32>, text: &str| {
+            let mut values: Vec<i32> = trie.matches::<SegmentMatcher<Arc<str>>>(&path(text)).map(|(_, value)| *value).collect();
+            values.sort_unstable();
+            values
+        };
+        assert_eq!(vec![1, 4], get(&trie, "/path/to"));
+        assert_eq!(vec![2], get(&trie, "/pathology"));
+        assert_eq!(vec![3, 4, 5], get(&trie, "/path/b/c"));
+        assert_eq!(vec![4, 5], get(&trie, "/path/b/x/c"));
+        assert_eq!(vec![4], get(&trie, "/path"));
+        assert_eq!(vec![5], get(&trie, "/c"));
+        assert_eq!(Vec::<i32>::new(), get(&trie, "/pathology/x"));
+        assert_eq!(Some(&3), trie.get_exact(&path("/path/*/c")));
+        assert_eq!(Some(2), trie.get::<SegmentMatcher<Arc<str>>>(&path("/pathology").segments[..]));
+
+        // Reversed DNS labels, suffixes sharing nodes
+        let labels = |name: &str| SegmentKey::literal(name.rsplit('.').map(String::from));
+        let mut trie = SegmentTrie::new();
+        trie.insert(labels("example.com"), "example");
+        trie.insert(labels("api.example.com"), "api");
+        let zones: Vec<&str> = trie.matches::<PrefixMatcher<Segment<String>>>(&labels("v1.api.example.com"))
+            .map(|(_, value)| *value)
+            .collect();
+        assert_eq!(vec!["example", "api"], zones);
+
+        let mut trie = SegmentTrie::new();
+        trie.insert(SegmentKey::new(vec![Segment::Literal(7_u32), Segment::Any]), 1);
+        assert_eq!(Some(1), trie.get::<SegmentMatcher<u32>>(&SegmentKey::literal(vec![7, 8])));
+        assert_eq!(None, trie.get::<SegmentMatcher<u32>>(&SegmentKey::literal(vec![7])));
+    }
+}
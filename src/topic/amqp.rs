@@ -0,0 +1,123 @@
+//! AMQP topic exchange binding keys: `*` matches exactly one word and `#` zero or more words,
+//! anywhere in the key (`a.#.z` matches `a.z`)
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+use serde::{Serialize, Deserialize};
+use crate::fold::Folding;
+use crate::key::{text_key_prefix, KeyPrefix, KeyQuery};
+use crate::matcher::StateSequence;
+use crate::topic::TopicSyntax;
+
+const SYNTAX: TopicSyntax = TopicSyntax {
+    separator: '.',
+    single_level: '*',
+    multi_level: '#',
+    multi_level_last: false,
+    excluded: None,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AmqpRoutingKey {
+    pub key: String,
+}
+
+impl AmqpRoutingKey {
+    pub fn new(key: &str) -> Self {
+        Self {
+            key: key.to_string()
+        }
+    }
+}
+
+///////////////////////////
+
+impl Display for AmqpRoutingKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("{}", self.key))
+    }
+}
+
+impl KeyPrefix for AmqpRoutingKey {
+
+    text_key_prefix!(key);
+
+    /// Only literal chars get folded, match keys included
+    #[inline]
+    fn folded(&self, folding: &Folding) -> Self {
+        Self {
+            key: SYNTAX.fold(&self.key, folding)
+        }
+    }
+
+    #[inline]
+    fn prefix_boundary(&self, index: usize) -> usize {
+        SYNTAX.prefix_boundary(self.symbols(), index)
+    }
+
+    #[inline]
+    fn compiled(&self) -> Vec<Arc<StateSequence>> {
+        SYNTAX.compile(&self.symbols().collect::<Vec<_>>())
+    }
+}
+
+impl KeyQuery<AmqpRoutingKey> for str {
+
+    #[inline]
+    fn query_symbols(&self) -> impl Iterator<Item = char> + Clone + '_ {
+        self.chars()
+    }
+
+    #[inline]
+    fn to_key(&self) -> AmqpRoutingKey {
+        AmqpRoutingKey::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::trie::Trie;
+    use crate::topic::TopicMatcher;
+    use crate::topic::amqp::*;
+
+    #[test]
+    fn amqp_routing_key_test() {
+        let mut trie: Trie<AmqpRoutingKey, Vec<&str>> = Trie::new();
+        trie.insert(AmqpRoutingKey::new("*.orange.*"), vec!["q1"]);
+        trie.insert(AmqpRoutingKey::new("*.*.rabbit"), vec!["q2"]);
+        trie.insert(AmqpRoutingKey::new("lazy.#"), vec!["q3"]);
+        trie.insert(AmqpRoutingKey::new("a.#.z"), vec!["q4"]);
+        trie.insert(AmqpRoutingKey::new("#.z"), vec!["q5"]);
+        trie.insert(AmqpRoutingKey::new("#"), vec!["q6"]);
+        trie.insert(AmqpRoutingKey::new("a.#.#.z"), vec!["q7"]);
+
+        let queues = |trie: &Trie<AmqpRoutingKey, Vec<&'static str>>, key: &str| {
+            let mut queues = trie.get_merge::<TopicMatcher>(&AmqpRoutingKey::new(key)).unwrap_or_default();
+            queues.sort_unstable();
+            queues
+        };
+        assert_eq!(vec!["q1", "q2", "q6"], queues(&trie, "quick.orange.rabbit"));
+        assert_eq!(vec!["q1", "q3", "q6"], queues(&trie, "lazy.orange.elephant"));
+        assert_eq!(vec!["q3", "q6"], queues(&trie, "lazy"));
+        assert_eq!(vec!["q6"], queues(&trie, "quick.orange.male.rabbit"));
+        assert_eq!(vec!["q4", "q5", "q6", "q7"], queues(&trie, "a.z"));
+        assert_eq!(vec!["q4", "q5", "q6", "q7"], queues(&trie, "a.b.c.z"));
+        assert_eq!(vec!["q5", "q6"], queues(&trie, "z"));
+        assert_eq!(vec!["q5", "q6"], queues(&trie, "b.zz.z"));
+        assert_eq!(vec!["q6"], queues(&trie, "a.zz"));
+
+        // `#` captures the words in between, if any
+        for (query, words) in [("a.b.c.z", "b.c"), ("a.z", "")] {
+            let query = AmqpRoutingKey::new(query);
+            let mut matches = trie.matches::<TopicMatcher>(&query);
+            while let Some((key, _)) = matches.next() {
+                if key.key == "a.#.z" {
+                    assert_eq!(words, matches.captures()[0].slice(&query.key));
+                }
+            }
+        }
+
+        for (key, value) in &trie {
+            assert_eq!(Some(value), trie.get_exact(&key));
+        }
+    }
+}
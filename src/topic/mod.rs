@@ -0,0 +1,101 @@
+//! Pub/sub topic keys whose wildcards match whole levels (optional)
+pub mod mqtt;
+pub mod amqp;
+
+use std::sync::Arc;
+use crate::fold::Folding;
+use crate::glob::GlobMatcher;
+use crate::matcher::{MatchType, SequenceBuilder, StateSequence};
+
+/// Topic wildcards compile into glob tokens, so the glob machine matches them
+pub type TopicMatcher = GlobMatcher;
+
+/// Wildcard syntax of a topic key type
+pub(crate) struct TopicSyntax {
+    pub separator: char,
+    /// Wildcard matching exactly one level
+    pub single_level: char,
+    /// Wildcard matching any number of levels
+    pub multi_level: char,
+    /// Whether the multi level wildcard is only allowed as the last level
+    pub multi_level_last: bool,
+    /// First char of the topics which leading wildcards never match
+    pub excluded: Option<char>,
+}
+
+impl TopicSyntax {
+
+    /// Whether `level`, at `index` among `count` levels, is a multi level wildcard
+    fn is_multi_level(&self, level: &[char], index: usize, count: usize) -> bool {
+        level == [self.multi_level] && (!self.multi_level_last || index + 1 == count)
+    }
+
+    /// Compiles the wildcard levels of `chars`; wildcards within a level are literals
+    pub fn compile(&self, chars: &[char]) -> Vec<Arc<StateSequence>> {
+        let mut builder = SequenceBuilder::new();
+        let levels: Vec<&[char]> = chars.split(|ch| *ch == self.separator).collect();
+        for (index, level) in levels.iter().enumerate() {
+            let excluded = if index == 0 { self.excluded } else { None };
+            if *level == [self.single_level] {
+                builder.construct(MatchType::AnyLevel { separator: self.separator, excluded });
+            }
+            else if self.is_multi_level(level, index, levels.len()) {
+                if index > 0 && index + 1 == levels.len() {
+                    // The preceding separator is optional too
+                    builder.pop_literal();
+                    builder.construct(MatchType::SubLevels { separator: self.separator });
+                }
+                else {
+                    builder.construct(MatchType::AnyLevels { separator: self.separator, excluded });
+                }
+            }
+            else {
+                builder.literals(level);
+            }
+            if index + 1 < levels.len() {
+                builder.literal(self.separator);
+            }
+        }
+        builder.build()
+    }
+
+    /// Folds the literal chars of `topic`. Topics have no escaping, so the levels folding into
+    /// wildcards are left as they are
+    pub fn fold(&self, topic: &str, folding: &Folding) -> String {
+        let syntax = |ch| ch == self.separator || ch == self.single_level || ch == self.multi_level || Some(ch) == self.excluded;
+        folding.fold_pattern(topic, syntax, None)
+    }
+
+    /// Keys are only split before a separator, so every node holds whole levels, and never
+    /// after a multi level wildcard, which owns the separator following it
+    pub fn prefix_boundary(&self, chars: impl Iterator<Item = char>, index: usize) -> usize {
+        let mut boundary = 0;
+        // Whether the level read so far is the lone multi level wildcard
+        let mut multi_level = false;
+        let mut level_len = 0;
+        let mut len = 0;
+        for ch in chars {
+            if len > index {
+                return boundary;
+            }
+            if ch == self.separator {
+                if !multi_level {
+                    boundary = len;
+                }
+                level_len = 0;
+            }
+            else {
+                level_len += 1;
+            }
+            multi_level = level_len == 1 && ch == self.multi_level;
+            len += 1;
+        }
+        if len <= index {
+            // The key end
+            len
+        }
+        else {
+            boundary
+        }
+    }
+}
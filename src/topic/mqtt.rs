@@ -0,0 +1,117 @@
+//! MQTT topic filters: `+` matches exactly one level and a trailing `#` any remaining levels,
+//! the parent level included. Leading wildcards never match `$` topics, as `$SYS/...`
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+use serde::{Serialize, Deserialize};
+use crate::fold::Folding;
+use crate::key::{text_key_prefix, KeyPrefix, KeyQuery};
+use crate::matcher::StateSequence;
+use crate::topic::TopicSyntax;
+
+const SYNTAX: TopicSyntax = TopicSyntax {
+    separator: '/',
+    single_level: '+',
+    multi_level: '#',
+    multi_level_last: true,
+    excluded: Some('$'),
+};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MqttTopic {
+    pub topic: String,
+}
+
+impl MqttTopic {
+    pub fn new(topic: &str) -> Self {
+        Self {
+            topic: topic.to_string()
+        }
+    }
+}
+
+///////////////////////////
+
+impl Display for MqttTopic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("{}", self.topic))
+    }
+}
+
+impl KeyPrefix for MqttTopic {
+
+    text_key_prefix!(topic);
+
+    /// Only literal chars get folded, match keys included
+    #[inline]
+    fn folded(&self, folding: &Folding) -> Self {
+        Self {
+            topic: SYNTAX.fold(&self.topic, folding)
+        }
+    }
+
+    #[inline]
+    fn prefix_boundary(&self, index: usize) -> usize {
+        SYNTAX.prefix_boundary(self.symbols(), index)
+    }
+
+    #[inline]
+    fn compiled(&self) -> Vec<Arc<StateSequence>> {
+        SYNTAX.compile(&self.symbols().collect::<Vec<_>>())
+    }
+}
+
+impl KeyQuery<MqttTopic> for str {
+
+    #[inline]
+    fn query_symbols(&self) -> impl Iterator<Item = char> + Clone + '_ {
+        self.chars()
+    }
+
+    #[inline]
+    fn to_key(&self) -> MqttTopic {
+        MqttTopic::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::trie::Trie;
+    use crate::topic::TopicMatcher;
+    use crate::topic::mqtt::*;
+
+    #[test]
+    fn mqtt_topic_test() {
+        let mut trie: Trie<MqttTopic, Vec<u32>> = Trie::new();
+        trie.insert(MqttTopic::new("sport/tennis/player1"), vec![1]);
+        trie.insert(MqttTopic::new("sport/tennis/+"), vec![2]);
+        trie.insert(MqttTopic::new("sport/#"), vec![3]);
+        trie.insert(MqttTopic::new("+/+"), vec![4]);
+        trie.insert(MqttTopic::new("#"), vec![5]);
+        trie.insert(MqttTopic::new("$SYS/#"), vec![6]);
+        trie.insert(MqttTopic::new("sport/ten#"), vec![7]);
+        trie.insert(MqttTopic::new("sport/+/+/ranking"), vec![8]);
+
+        let subscribers = |trie: &Trie<MqttTopic, Vec<u32>>, topic: &str| {
+            let mut subscribers = trie.get_merge::<TopicMatcher>(&MqttTopic::new(topic)).unwrap_or_default();
+            subscribers.sort_unstable();
+            subscribers
+        };
+        assert_eq!(vec![1, 2, 3, 5], subscribers(&trie, "sport/tennis/player1"));
+        assert_eq!(vec![3, 5, 8], subscribers(&trie, "sport/tennis/player1/ranking"));
+        assert_eq!(vec![3, 4, 5], subscribers(&trie, "sport/tennis"));
+        assert_eq!(vec![3, 5], subscribers(&trie, "sport"));
+        assert_eq!(vec![3, 4, 5], subscribers(&trie, "sport/"));
+        assert_eq!(vec![4, 5], subscribers(&trie, "/finance"));
+        assert_eq!(vec![3, 4, 5, 7], subscribers(&trie, "sport/ten#"));
+        assert_eq!(vec![6], subscribers(&trie, "$SYS/broker/uptime"));
+        assert_eq!(vec![6], subscribers(&trie, "$SYS"));
+        assert_eq!(vec![5], subscribers(&trie, "finance/stock/ibm"));
+
+        // Nodes hold whole levels
+        for (key, value) in &trie {
+            assert_eq!(Some(value), trie.get_exact(&key));
+        }
+        assert_eq!(Some(vec![3]), trie.remove(&MqttTopic::new("sport/#")));
+        assert_eq!(vec![5], subscribers(&trie, "sport"));
+    }
+}
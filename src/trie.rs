@@ -1,11 +1,17 @@
 //! The Trie trait(s)
-use std::fmt::Debug;
+use std::borrow::Cow;
+use std::fmt::{Debug, Formatter};
+use std::iter::FromIterator;
 use std::marker::PhantomData;
-use std::slice::Iter;
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
 use serde::{Serialize, Deserialize};
 use crate::node::RFRNode;
-use crate::key::{TrieKey, KeyPrefix, ValueMerge};
-use crate::matcher::PushdownStateMachine;
+use crate::fold::Folding;
+use crate::entry::{Entry, OccupiedEntry, VacantEntry};
+use crate::iterator::{cmp_keys, IntoIter, Iter, IterPrefix, Keys, Range, TrieIterator, Values};
+use crate::key::{TrieKey, KeyPrefix, KeyQuery, QuerySymbols, ValueMerge};
+use crate::matcher::{Capture, PushdownStateMachine, StateSequence};
 
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -14,10 +20,19 @@ pub struct Trie<K, V> where
 {
     size: usize,
     node: RFRNode<K, V>,
+    #[serde(default)]
+    folding: Folding,
     _phantom_k: PhantomData<K>,
     _phantom_v: PhantomData<V>,
 }
 
+impl<K: KeyPrefix + Clone, V: Clone> Default for Trie<K, V>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<K: KeyPrefix + Clone, V: Clone> Trie<K, V>
 {
     /// Creates new trie
@@ -25,13 +40,67 @@ impl<K: KeyPrefix + Clone, V: Clone> Trie<K, V>
         Self {
             size: 0,
             node: RFRNode::new(),
+            folding: Folding::default(),
             _phantom_k: Default::default(),
             _phantom_v: Default::default()
         }
     }
 
+    /// Creates new trie folding both its stored keys and the keys it is queried with
+    pub fn with_folding(folding: Folding) -> Self {
+        Self {
+            folding,
+            ..Self::new()
+        }
+    }
+
+    /// The key as compared by this trie
+    #[inline]
+    pub(crate) fn fold<'k>(&self, key: &'k K) -> Cow<'k, K> {
+        if self.folding.is_identity() {
+            Cow::Borrowed(key)
+        }
+        else {
+            Cow::Owned(key.folded(&self.folding))
+        }
+    }
+
+    /// The symbols of a `key` query as compared by this trie. Only folding tries build a key out of it
+    #[inline]
+    fn query<'q>(&self, key: &'q (impl KeyQuery<K> + ?Sized)) -> QuerySymbols<impl Iterator<Item = K::Symbol> + Clone + 'q> {
+        if self.folding.is_identity() {
+            QuerySymbols::Borrowed(key.query_symbols())
+        }
+        else {
+            let folded = key.to_key().folded_query(&self.folding);
+            QuerySymbols::Folded(folded.symbols().collect::<Vec<_>>().into_iter())
+        }
+    }
+
+    /// Number of stored keys
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// Removes all keys
+    #[inline]
+    pub fn clear(&mut self) {
+        self.size = 0;
+        self.node = RFRNode::new();
+    }
+
     #[inline]
     pub fn insert(&mut self, key: K, value: V) -> Option<V>  {
+        let key = match self.fold(&key) {
+            Cow::Owned(folded) => folded,
+            Cow::Borrowed(_) => key,
+        };
         let result = self.node.insert(TrieKey::new(key), Some(value));
         if result.is_none() {
             self.size += 1;
@@ -39,31 +108,299 @@ impl<K: KeyPrefix + Clone, V: Clone> Trie<K, V>
         result
     }
 
+    /// Removes an exact (literal or pattern) key, returning its value
+    #[inline]
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let result = self.node.remove(&TrieKey::new(self.fold(key).into_owned()));
+        if result.is_some() {
+            self.size -= 1;
+        }
+        result
+    }
+
+    #[inline]
+    pub fn get<M: PushdownStateMachine<K::Symbol> + Clone>(&self, key: &(impl KeyQuery<K> + ?Sized)) -> Option<V>  {
+        self.node.get::<M, _>(self.query(key))
+    }
+
+    /// Lazily iterates over every stored key accepting `key`, along with its value
+    #[inline]
+    pub fn matches<'a, 'q, M: PushdownStateMachine<K::Symbol> + Clone>(&'a self, key: &'q (impl KeyQuery<K> + ?Sized))
+        -> TrieIterator<'a, K, V, M, impl Iterator<Item = K::Symbol> + Clone + 'q>
+    {
+        let matches = self.node.lookup::<M, _>(self.query(key));
+        if self.folding.is_identity() {
+            matches
+        }
+        else {
+            matches.unfolding(key.to_key(), self.folding)
+        }
+    }
+
+    /// Collects every stored key accepting `key` along with its value and cost, cheapest first
+    /// (ties in key order)
+    pub fn matches_ranked<M: PushdownStateMachine<K::Symbol> + Clone>(&self, key: &(impl KeyQuery<K> + ?Sized)) -> Vec<(K, &V, usize)> {
+        let mut matches = self.matches::<M>(key);
+        let mut ranked = Vec::new();
+        while let Some((stored_key, value)) = matches.next() {
+            ranked.push((stored_key, value, matches.cost()));
+        }
+        ranked.sort_by(|(first, _, first_cost), (second, _, second_cost)| first_cost.cmp(second_cost).then_with(|| cmp_keys(first, second)));
+        ranked
+    }
+
+    /// Gets the first value whose stored key accepts `key`, along with that stored key
+    #[inline]
+    pub fn get_with_key<M: PushdownStateMachine<K::Symbol> + Clone>(&self, key: &(impl KeyQuery<K> + ?Sized)) -> Option<(K, &V)>  {
+        self.node.get_with_key::<M, _>(self.query(key))
+    }
+
+    /// Gets the first value whose stored key accepts `key`, along with that stored key
+    /// and the spans of `key` consumed by each of its wildcards
+    #[inline]
+    pub fn get_with_captures<M: PushdownStateMachine<K::Symbol> + Clone>(&self, key: &(impl KeyQuery<K> + ?Sized)) -> Option<(K, &V, Vec<Capture>)>  {
+        let mut matches = self.matches::<M>(key);
+        let (matched_key, value) = matches.next()?;
+        Some((matched_key, value, matches.captures().to_vec()))
+    }
+
+    /// Every stored key the `key` query starts with, along with its value, shortest first.
+    /// Keys are compared literally, as routing tables do (no matcher involved)
     #[inline]
-    pub fn get<M: PushdownStateMachine + Clone>(&self, key: &K) -> Option<V>  {
-        self.node.get::<M>(key)
+    pub fn prefixes_of(&self, key: &(impl KeyQuery<K> + ?Sized)) -> Vec<(K, &V)> {
+        self.node.prefixes_of(self.query(key))
     }
 
+    /// The longest stored key the `key` query starts with, along with its value
     #[inline]
-    pub fn get_merge<M: PushdownStateMachine + Clone>(&self, key: &K) -> Option<V>
+    pub fn longest_prefix(&self, key: &(impl KeyQuery<K> + ?Sized)) -> Option<(K, &V)> {
+        self.node.longest_prefix(self.query(key))
+    }
+
+    /// Inverse lookup: the stored keys, taken as literal match keys, which the compiled `pattern`
+    /// accepts when run by `M`, in order. Subtrees the pattern cannot reach are pruned
+    #[allow(clippy::ptr_arg)]
+    pub fn matched_by<M: PushdownStateMachine<K::Symbol> + Clone>(&self, pattern: &Vec<Arc<StateSequence<K::Symbol>>>) -> Vec<(K, &V)> {
+        let mut matcher = M::new();
+        matcher.step_in(pattern);
+        self.node.matched_by(matcher)
+    }
+
+    /// Iterates over the stored keys starting with `prefix` (fully reconstructed) and their values, in order.
+    /// Keys are compared literally (no matcher involved)
+    pub fn iter_prefix(&self, prefix: &(impl KeyQuery<K> + ?Sized)) -> IterPrefix<'_, K, V> {
+        IterPrefix::new(&self.node, self.query(prefix).collect())
+    }
+
+    /// Number of stored keys starting with `prefix`
+    pub fn count_prefix(&self, prefix: &(impl KeyQuery<K> + ?Sized)) -> usize {
+        self.node.prefix_roots(self.query(prefix))
+            .iter()
+            .flat_map(|(_, root)| root.iter())
+            .map(|root| root.count())
+            .sum()
+    }
+
+    /// Removes every key starting with `prefix`, returning them in a trie of their own
+    pub fn remove_prefix(&mut self, prefix: &(impl KeyQuery<K> + ?Sized)) -> Self {
+        let roots = self.node.remove_prefix(&K::empty(), self.query(prefix));
+        let removed = self.new_from_subtrees(roots);
+        self.size -= removed.size;
+        removed
+    }
+
+    /// A trie holding a copy of the keys starting with `prefix`
+    pub fn subtrie(&self, prefix: &(impl KeyQuery<K> + ?Sized)) -> Self {
+        let roots = self.node.prefix_roots(self.query(prefix))
+            .into_iter()
+            .flat_map(|(key, root)| root.iter().map(move |root| (key.clone(), root.as_ref().clone())))
+            .collect();
+        self.new_from_subtrees(roots)
+    }
+
+    /// A trie folding as this one does, holding the `subtrees` (each along with the full key of its parent)
+    fn new_from_subtrees(&self, subtrees: Vec<(K, RFRNode<K, V>)>) -> Self {
+        let mut trie = Self::with_folding(self.folding);
+        for (parent_key, subtree) in subtrees {
+            trie.size += subtree.count();
+            trie.node.insert_subtree(&parent_key, subtree);
+        }
+        trie
+    }
+
+    /// Iterates over the stored keys within `range` (fully reconstructed) and their values, in order,
+    /// from either end. Keys are compared literally, symbol by symbol
+    pub fn range(&self, range: impl RangeBounds<K>) -> Range<'_, K, V> {
+        let start = match range.start_bound() {
+            Bound::Included(key) => Bound::Included(self.fold(key).into_owned()),
+            Bound::Excluded(key) => Bound::Excluded(self.fold(key).into_owned()),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(key) => Bound::Included(self.fold(key).into_owned()),
+            Bound::Excluded(key) => Bound::Excluded(self.fold(key).into_owned()),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        Range::new(&self.node, (start, end))
+    }
+
+    /// The first stored key in order, along with its value
+    #[inline]
+    pub fn first(&self) -> Option<(K, &V)> {
+        self.iter().next()
+    }
+
+    /// The last stored key in order, along with its value
+    #[inline]
+    pub fn last(&self) -> Option<(K, &V)> {
+        self.range(..).next_back()
+    }
+
+    /// The stored key right before `key` in order (`key` itself excluded), along with its value
+    #[inline]
+    pub fn predecessor(&self, key: &(impl KeyQuery<K> + ?Sized)) -> Option<(K, &V)> {
+        self.range(..key.to_key()).next_back()
+    }
+
+    /// The stored key right after `key` in order (`key` itself excluded), along with its value
+    #[inline]
+    pub fn successor(&self, key: &(impl KeyQuery<K> + ?Sized)) -> Option<(K, &V)> {
+        self.range((Bound::Excluded(key.to_key()), Bound::Unbounded)).next()
+    }
+
+    /// Gets the value stored under the exact `key`, compared literally (no matcher involved)
+    #[inline]
+    pub fn get_exact(&self, key: &K) -> Option<&V> {
+        let path = self.node.locate(&TrieKey::new(self.fold(key).into_owned()))?;
+        self.node.node_at(&path).value.as_ref()
+    }
+
+    /// Gets a mutable reference to the value stored under the exact `key`
+    #[inline]
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let path = self.node.locate(&TrieKey::new(self.fold(key).into_owned()))?;
+        self.node.node_at_mut(&path).value.as_mut()
+    }
+
+    /// Gets the entry of the exact `key` for in-place manipulation
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V> {
+        let key = self.fold(&key).into_owned();
+        let path = self.node.locate(&TrieKey::new(key.clone()))
+            .filter(|path| self.node.node_at(path).value.is_some());
+        match path {
+            Some(path) => Entry::Occupied(OccupiedEntry {
+                key,
+                value: self.node.node_at_mut(&path).value.as_mut().unwrap(),
+            }),
+            None => Entry::Vacant(VacantEntry {
+                key,
+                trie: self,
+            }),
+        }
+    }
+
+    /// Checks whether the exact `key` is stored, compared literally (no matcher involved)
+    #[inline]
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get_exact(key).is_some()
+    }
+
+    #[inline]
+    pub fn get_merge<M: PushdownStateMachine<K::Symbol> + Clone>(&self, key: &(impl KeyQuery<K> + ?Sized)) -> Option<V>
+    where V: ValueMerge + Debug
+    {
+        self.node.get_merge::<M, _>(self.query(key))
+    }
+
+    /// Merges all the values whose stored keys accept `key`, reporting the contributing stored keys
+    #[inline]
+    pub fn get_merge_with_keys<M: PushdownStateMachine<K::Symbol> + Clone>(&self, key: &(impl KeyQuery<K> + ?Sized)) -> Option<(V, Vec<K>)>
     where V: ValueMerge + Debug
     {
-        self.node.get_merge::<M>(key)
+        self.node.get_merge_with_keys::<M, _>(self.query(key))
     }
 
+    /// Iterates over the stored keys (fully reconstructed) and values, in order
     #[inline]
-    /// Inmutable slice iterator
-    pub fn iter(&self) -> Iter<'_, Box<RFRNode<K, V>>> {
-        self.node.children.iter()
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter::new(&self.node, self.size)
     }
 
+    /// Iterates over the stored keys, in order
     #[inline]
-    pub fn foreach<F>(&self, f: F) -> ()
+    pub fn keys(&self) -> Keys<'_, K, V> {
+        Keys { inner: self.iter() }
+    }
+
+    /// Iterates over the stored values, in key order
+    #[inline]
+    pub fn values(&self) -> Values<'_, K, V> {
+        Values { inner: self.iter() }
+    }
+
+    #[inline]
+    pub fn foreach<F>(&self, f: F)
         where F: Fn((usize, &K, &Option<V>))
     {
-        for item in self.iter() {
-            f( (1 as usize, &item.node_key.key, &item.value) );
+        for item in self.node.iter() {
+            f( (1, &item.node_key.key, &item.value) );
             item.foreach(2, &f);
         }
     }
 }
+
+impl<K: KeyPrefix + Clone + Debug, V: Clone + Debug> Debug for Trie<K, V>
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<K: KeyPrefix + Clone + PartialEq, V: Clone + PartialEq> PartialEq for Trie<K, V>
+{
+    fn eq(&self, other: &Self) -> bool {
+        // The compressed structure is canonical for a given set of keys, compared as folded
+        self.size == other.size && self.folding == other.folding && self.node == other.node
+    }
+}
+
+impl<K: KeyPrefix + Clone + Eq, V: Clone + Eq> Eq for Trie<K, V> {}
+
+impl<K: KeyPrefix + Clone, V: Clone> FromIterator<(K, V)> for Trie<K, V>
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut trie = Self::new();
+        trie.extend(iter);
+        trie
+    }
+}
+
+impl<K: KeyPrefix + Clone, V: Clone> Extend<(K, V)> for Trie<K, V>
+{
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K: KeyPrefix + Clone, V: Clone> IntoIterator for Trie<K, V>
+{
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+
+    /// Owning iterator yielding full keys in order
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter::new(self.node, self.size)
+    }
+}
+
+impl<'a, K: KeyPrefix + Clone, V: Clone> IntoIterator for &'a Trie<K, V>
+{
+    type Item = (K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}